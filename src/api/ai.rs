@@ -1,14 +1,22 @@
-use crate::api::conversation::{Conversation, Message};
+use crate::api::conversation::{Conversation, Message, Usage};
+use crate::api::provider::{emit_whole_message, ChatProvider, ChatResponse, ProviderOptions, ResponseMetadata, StreamEvent};
+use crate::api::error::{read_json, ApiError};
+use crate::api::sse::SseDecoder;
+use async_trait::async_trait;
 use serde_json::json;
 use anyhow::{Result, Context};
 
 pub struct DeepseekAi {
     api_key: String,
+    url: String,
+    stream: bool,
+    options: ProviderOptions,
 }
 
 const DEEPSEEK_URL: &str = "https://api.deepseek.com/chat/completions";
+const DEEPSEEK_MODEL: &str = "deepseek-reasoner";
 
-fn extract_field(response_json: &serde_json::Value, field_path: &[&str]) -> Result<String> {
+pub(crate) fn extract_field(response_json: &serde_json::Value, field_path: &[&str]) -> Result<String> {
     let mut current = response_json;
     for &field in field_path {
         current = current.get(field).context(format!("Field {} not found", field))?;
@@ -22,46 +30,195 @@ fn extract_field(response_json: &serde_json::Value, field_path: &[&str]) -> Resu
     }
 }
 
+pub(crate) fn extract_usage(response_json: &serde_json::Value) -> Option<Usage> {
+    let usage = response_json.get("usage")?;
+    Some(Usage {
+        prompt_tokens: usage.get("prompt_tokens").and_then(|v| v.as_u64()),
+        completion_tokens: usage.get("completion_tokens").and_then(|v| v.as_u64()),
+        total_tokens: usage.get("total_tokens").and_then(|v| v.as_u64()),
+    })
+}
+
+// Accumulates the `chat.completion.chunk` objects of a streamed reply, forwarding
+// every non-empty delta to the caller as it comes in.
+#[derive(Debug, Default)]
+pub(crate) struct StreamedReply {
+    pub(crate) content: String,
+    pub(crate) reasoning: String,
+    pub(crate) metadata: ResponseMetadata,
+}
+
+impl StreamedReply {
+    pub(crate) fn apply_chunk(&mut self, chunk: &serde_json::Value, on_event: &mut (dyn FnMut(StreamEvent) + Send)) {
+        let choice = &chunk["choices"][0];
+        if let Some(delta) = choice["delta"]["reasoning_content"].as_str().filter(|d| !d.is_empty()) {
+            self.reasoning.push_str(delta);
+            on_event(StreamEvent::Reasoning(delta.to_string()));
+        }
+        if let Some(delta) = choice["delta"]["content"].as_str().filter(|d| !d.is_empty()) {
+            self.content.push_str(delta);
+            on_event(StreamEvent::Content(delta.to_string()));
+        }
+        if let Some(model) = chunk["model"].as_str() {
+            self.metadata.model = Some(model.to_string());
+        }
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.metadata.finish_reason = Some(reason.to_string());
+        }
+        if let Some(usage) = extract_usage(chunk).filter(|_| chunk["usage"].is_object()) {
+            self.metadata.usage = Some(usage);
+        }
+    }
+
+    pub(crate) fn into_message(self) -> Message {
+        Message::Assistant {
+            content: self.content,
+            reasoning: Some(self.reasoning).filter(|r| !r.is_empty()),
+            usage: self.metadata.usage,
+        }
+    }
+}
+
+// a `data:` payload of a streamed completion; errors may also arrive this way
+pub(crate) fn parse_stream_chunk(data: &str) -> Result<serde_json::Value, ApiError> {
+    let chunk: serde_json::Value = serde_json::from_str(data).map_err(ApiError::malformed)?;
+    if !chunk["error"].is_null() {
+        return Err(ApiError::from_response(200, data));
+    }
+    Ok(chunk)
+}
+
 impl DeepseekAi {
     pub fn new(api_key: String) -> Self {
-        DeepseekAi { api_key }
+        DeepseekAi {
+            api_key,
+            url: DEEPSEEK_URL.to_string(),
+            stream: false,
+            options: ProviderOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ProviderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_url(mut self, url: String) -> Self {
+        self.url = url;
+        self
     }
 
-    pub async fn chat(&self, conv: Conversation) -> Result<Message> {
-        let client = reqwest::Client::new();
-        let payload = json!({
-            "messages": conv.to_messages(),
-            "model": "deepseek-reasoner",
+    // ask for server-sent events instead of a single JSON body in `chat_stream`
+    pub fn with_stream(mut self, stream: bool) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    fn payload(&self, conv: &Conversation, stream: bool) -> serde_json::Value {
+        let model = self.options.model(DEEPSEEK_MODEL);
+        // deepseek-reasoner rejects two user or two assistant messages in a row
+        let messages = if model.contains("reasoner") {
+            conv.to_merged_messages()
+        } else {
+            conv.to_messages()
+        };
+        let mut payload = json!({
+            "messages": messages,
+            "model": model,
             "frequency_penalty": 0,
             "max_tokens": 2048,
             "presence_penalty": 0,
             "response_format": {
                 "type": "text"
-            }
+            },
+            "stream": stream
         });
-        let response = client
-            .post(DEEPSEEK_URL)
-            .header("Content-Type", "application/json")
-            .header("Accept", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to send request to Deepseek API")?;
+        self.options.params.apply_to(&mut payload);
+        payload
+    }
 
-        let response_json = response
-            .json::<serde_json::Value>()
+    async fn send(&self, payload: &serde_json::Value, accept: &str) -> Result<reqwest::Response> {
+        self.options.retry
+            .send("Deepseek API", || {
+                self.options.client
+                    .post(&self.url)
+                    .header("Content-Type", "application/json")
+                    .header("Accept", accept)
+                    .header("Authorization", format!("Bearer {}", self.api_key))
+                    .json(payload)
+                    .send()
+            })
             .await
-            .context("Failed to parse response from Deepseek API")?;
+    }
+}
 
-        let content = extract_field(&response_json["choices"][0], &["message", "content"]).unwrap();
-        let reasoning = extract_field(&response_json["choices"][0], &["message", "reasoning_content"]).unwrap();
-        let usage = extract_field(&response_json, &["usage", "completion_tokens"]).unwrap();
+#[async_trait]
+impl ChatProvider for DeepseekAi {
+    fn name(&self) -> &str {
+        "Deepseek AI"
+    }
 
-        Ok(Message::Assistant(format!("{}\n\nReasoning: {}\n\nUsage: {}", content, reasoning, usage)))
+    fn request_body(&self, conv: &Conversation) -> serde_json::Value {
+        self.payload(conv, self.stream)
     }
-}
 
+    async fn chat(&self, conv: Conversation) -> Result<ChatResponse> {
+        let response = self.send(&self.payload(&conv, false), "application/json").await?;
+        let response_json = read_json(response).await?;
+
+        let content = extract_field(&response_json["choices"][0], &["message", "content"]).map_err(ApiError::malformed)?;
+        let reasoning = extract_field(&response_json["choices"][0], &["message", "reasoning_content"]).ok();
+
+        let metadata = ResponseMetadata {
+            model: response_json["model"].as_str().map(|s| s.to_string()),
+            finish_reason: response_json["choices"][0]["finish_reason"].as_str().map(|s| s.to_string()),
+            usage: extract_usage(&response_json),
+        };
+
+        Ok(ChatResponse {
+            message: Message::Assistant {
+                content,
+                reasoning: reasoning.filter(|r| !r.is_empty()),
+                usage: metadata.usage.clone(),
+            },
+            metadata,
+        })
+    }
+
+    async fn chat_stream(
+        &self,
+        conv: Conversation,
+        on_event: &mut (dyn FnMut(StreamEvent) + Send),
+    ) -> Result<ChatResponse> {
+        if !self.stream {
+            let response = self.chat(conv).await?;
+            emit_whole_message(&response.message, on_event);
+            return Ok(response);
+        }
+
+        let mut response = self.send(&self.request_body(&conv), "text/event-stream").await?;
+        let mut decoder = SseDecoder::default();
+        let mut reply = StreamedReply::default();
+        let mut events = Vec::new();
+        while let Some(chunk) = response.chunk().await.context("Failed to read Deepseek response stream")? {
+            events.extend(decoder.push(&chunk));
+            for data in events.drain(..) {
+                if data != "[DONE]" {
+                    reply.apply_chunk(&parse_stream_chunk(&data)?, on_event);
+                }
+            }
+        }
+        if let Some(data) = decoder.finish().filter(|data| data != "[DONE]") {
+            reply.apply_chunk(&parse_stream_chunk(&data)?, on_event);
+        }
+
+        let metadata = reply.metadata.clone();
+        Ok(ChatResponse {
+            message: reply.into_message(),
+            metadata,
+        })
+    }
+}
 
 
 mod test {
@@ -97,10 +254,14 @@ mod test {
     }
             "#;
 
+    // sends a real request with the configured API key and a local chat.md, run it with
+    // `cargo test -- --ignored`
     #[tokio::test]
+    #[ignore]
     async fn test_deepseek_ai() {
         use crate::api::setter::read_deepseek_api;
         use crate::api::conversation::{Conversation, Message};
+        use crate::api::provider::ChatProvider;
         use crate::api::md_paraser::parse_markdown_file;
         
         // Get API key and initialize AI
@@ -111,8 +272,8 @@ mod test {
         let conv: Conversation = parse_markdown_file("chat.md").unwrap();
         
         // Get response from Deepseek
-        match ai.chat(conv).await {
-            Ok(Message::Assistant(content)) => {
+        match ai.chat(conv).await.map(|response| response.message) {
+            Ok(Message::Assistant { content, .. }) => {
                 println!("\nDeepseek Response Success:");
                 println!("------------------------");
                 println!("{}", content);
@@ -146,6 +307,118 @@ mod test {
         assert_eq!(usage, "814");
     }
 
+    #[test]
+    fn test_extract_usage() {
+        let response_json: serde_json::Value = serde_json::from_str(SAMPLE_JSON).unwrap();
+        let usage = api::ai::extract_usage(&response_json).unwrap();
+        assert_eq!(usage.prompt_tokens, Some(43));
+        assert_eq!(usage.completion_tokens, Some(814));
+        assert_eq!(usage.total_tokens, Some(857));
+    }
+
+    #[allow(dead_code)]
+    const SAMPLE_STREAM: &str = concat!(
+        "data: {\"id\":\"1\",\"model\":\"deepseek-reasoner\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"reasoning_content\":\"Compare\"},\"finish_reason\":null}]}\n\n",
+        ": keep-alive\n\n",
+        "data: {\"id\":\"1\",\"model\":\"deepseek-reasoner\",\"choices\":[{\"index\":0,\"delta\":{\"content\":null,\"reasoning_content\":\" tenths.\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"1\",\"model\":\"deepseek-reasoner\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"9.8\",\"reasoning_content\":null},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"1\",\"model\":\"deepseek-reasoner\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" is bigger.\",\"reasoning_content\":null},\"finish_reason\":\"stop\"}],\"usage\":{\"prompt_tokens\":20,\"completion_tokens\":9,\"total_tokens\":29}}\n\n",
+        "data: [DONE]\n\n",
+    );
+
+    #[tokio::test]
+    async fn test_chat_stream_deltas() {
+        use crate::api::conversation::{Conversation, Message};
+        use crate::api::provider::{ChatProvider, StreamEvent};
+        use crate::api::test_server::{serve, CannedResponse};
+
+        let (base_url, requests) = serve(vec![CannedResponse::with_content_type(200, "text/event-stream", SAMPLE_STREAM)]).await;
+        let ai = api::ai::DeepseekAi::new("key".to_string())
+            .with_url(format!("{}/chat/completions", base_url))
+            .with_stream(true);
+        let mut conv = Conversation::new("You are a helper assistant".to_string());
+        conv.user("9.8 and 9.11, which is bigger?".to_string()).unwrap();
+
+        let mut events = Vec::new();
+        let response = ai.chat_stream(conv, &mut |event| events.push(event)).await.unwrap();
+        assert_eq!(events, vec![
+            StreamEvent::Reasoning("Compare".to_string()),
+            StreamEvent::Reasoning(" tenths.".to_string()),
+            StreamEvent::Content("9.8".to_string()),
+            StreamEvent::Content(" is bigger.".to_string()),
+        ]);
+        match response.message {
+            Message::Assistant { content, reasoning, usage } => {
+                assert_eq!(content, "9.8 is bigger.");
+                assert_eq!(reasoning.as_deref(), Some("Compare tenths."));
+                assert_eq!(usage.unwrap().completion_tokens, Some(9));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert_eq!(response.metadata.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(requests.await.unwrap()[0].json()["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn test_chat_api_errors() {
+        use crate::api::conversation::Conversation;
+        use crate::api::error::ApiError;
+        use crate::api::provider::{ChatProvider, ProviderOptions};
+        use crate::api::retry::RetryPolicy;
+        use crate::api::test_server::{serve, CannedResponse};
+
+        let (base_url, _) = serve(vec![
+            CannedResponse::json(402, r#"{"error":{"message":"Insufficient Balance","type":"unknown_error"}}"#),
+            CannedResponse::json(200, r#"{"choices":[]}"#),
+        ]).await;
+        let ai = api::ai::DeepseekAi::new("key".to_string())
+            .with_url(base_url)
+            .with_options(ProviderOptions { retry: RetryPolicy { max_attempts: 1, ..RetryPolicy::default() }, ..ProviderOptions::default() });
+        let conv = Conversation::new("You are a helper assistant".to_string());
+
+        let error = ai.chat(conv.clone()).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ApiError>(), Some(ApiError::Quota { .. })));
+        let error = ai.chat(conv).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ApiError>(), Some(ApiError::MalformedResponse { .. })));
+    }
+
+    #[test]
+    fn test_payload_params() {
+        use crate::api::conversation::Conversation;
+        use crate::api::params::ChatParams;
+        use crate::api::provider::ProviderOptions;
+
+        let conv = Conversation::new("You are a helper assistant".to_string());
+        let payload = api::ai::DeepseekAi::new("key".to_string()).payload(&conv, false);
+        assert_eq!(payload["model"], "deepseek-reasoner");
+        assert_eq!(payload["max_tokens"], 2048);
+        assert!(payload.get("temperature").is_none());
+
+        let params = ChatParams {
+            model: Some("deepseek-chat".to_string()),
+            temperature: Some(0.5),
+            max_tokens: Some(4096),
+            stop: vec!["###".to_string()],
+            ..ChatParams::default()
+        };
+        let payload = api::ai::DeepseekAi::new("key".to_string()).with_options(ProviderOptions { params, ..ProviderOptions::default() }).payload(&conv, false);
+        assert_eq!(payload["model"], "deepseek-chat");
+        assert_eq!(payload["temperature"], 0.5);
+        assert_eq!(payload["max_tokens"], 4096);
+        assert_eq!(payload["stop"][0], "###");
+
+        let mut conv = conv;
+        conv.set_role_order(crate::api::conversation::RoleOrder::Relaxed);
+        conv.user("first".to_string()).unwrap();
+        conv.user("second".to_string()).unwrap();
+        let payload = api::ai::DeepseekAi::new("key".to_string()).payload(&conv, false);
+        assert_eq!(payload["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(payload["messages"][1]["content"], "first\n\nsecond");
+        let chat = ChatParams { model: Some("deepseek-chat".to_string()), ..ChatParams::default() };
+        let payload = api::ai::DeepseekAi::new("key".to_string()).with_options(ProviderOptions { params: chat, ..ProviderOptions::default() }).payload(&conv, false);
+        assert_eq!(payload["messages"].as_array().unwrap().len(), 3);
+    }
+
     #[test]
     fn test_extract_field_error_invalid_path() {
         let response_json: serde_json::Value = serde_json::from_str(SAMPLE_JSON).unwrap();
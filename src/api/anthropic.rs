@@ -0,0 +1,332 @@
+use crate::api::ai::extract_field;
+use crate::api::conversation::{Conversation, Message, Usage};
+use crate::api::error::{read_json, ApiError};
+use crate::api::provider::{ChatProvider, ChatResponse, ProviderOptions, ResponseMetadata};
+use crate::api::secrets;
+use crate::api::setter::read_setting;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+// Anthropic Messages API (`/v1/messages`)
+pub struct AnthropicAi {
+    api_key: String,
+    model: String,
+    base_url: String,
+    max_tokens: u32,
+    options: ProviderOptions,
+}
+
+const ANTHROPIC_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const DEFAULT_MAX_TOKENS: u32 = 2048;
+
+impl AnthropicAi {
+    pub fn new(api_key: String, model: String) -> Self {
+        AnthropicAi {
+            api_key,
+            model,
+            base_url: ANTHROPIC_URL.to_string(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            options: ProviderOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ProviderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Build from the `ANTHROPIC_MODEL`, `ANTHROPIC_BASE_URL` and `ANTHROPIC_MAX_TOKENS`
+    /// settings, with the API key from `secrets::api_key`.
+    pub fn from_settings() -> Result<Self> {
+        let api_key = secrets::api_key("anthropic")?.ok_or_else(|| {
+            anyhow!("No Anthropic API key: set ANTHROPIC_API_KEY, a key_command in [anthropic] or add it to secrets.toml")
+        })?;
+        let model = read_setting("ANTHROPIC_MODEL")
+            .filter(|model| !model.is_empty())
+            .ok_or_else(|| anyhow!("anthropic.model is not set in the config"))?;
+        let mut provider = AnthropicAi::new(api_key, model);
+        if let Some(base_url) = read_setting("ANTHROPIC_BASE_URL").filter(|url| !url.is_empty()) {
+            provider = provider.with_base_url(base_url);
+        }
+        if let Some(max_tokens) = read_setting("ANTHROPIC_MAX_TOKENS") {
+            let max_tokens = max_tokens
+                .parse()
+                .with_context(|| format!("ANTHROPIC_MAX_TOKENS must be a number, got '{}'", max_tokens))?;
+            provider = provider.with_max_tokens(max_tokens);
+        }
+        Ok(provider)
+    }
+
+    fn endpoint(&self) -> String {
+        format!("{}/v1/messages", self.base_url.trim_end_matches('/'))
+    }
+}
+
+// The Messages API takes the system prompt as a top-level field and block
+// structured content, so it cannot reuse `Conversation::to_messages`. It also wants
+// user and assistant turns to alternate: consecutive messages of the same role
+// (possible in hand-edited chat files) become blocks of one message, and System
+// messages further down are added to the system prompt.
+pub(crate) fn to_anthropic_request(conv: &Conversation) -> (Option<String>, Vec<serde_json::Value>) {
+    let mut system_parts = Vec::new();
+    let mut messages: Vec<serde_json::Value> = Vec::new();
+    for message in conv.messages() {
+        let (role, text) = match message {
+            Message::System(text) => {
+                system_parts.push(text.trim().to_string());
+                continue;
+            }
+            Message::User(text) => ("user", text),
+            Message::Assistant { content, .. } => ("assistant", content),
+        };
+        let block = json!({"type": "text", "text": text});
+        match messages.last_mut() {
+            Some(last) if last["role"] == role => last["content"].as_array_mut().unwrap().push(block),
+            _ => messages.push(json!({"role": role, "content": [block]})),
+        }
+    }
+    let system = if system_parts.is_empty() {
+        None
+    } else {
+        Some(system_parts.join("\n\n"))
+    };
+    (system, messages)
+}
+
+// concatenate every block of the given type (`text` or `thinking`) of the reply
+fn collect_blocks(response_json: &serde_json::Value, block_type: &str) -> Result<String, ApiError> {
+    let blocks = response_json["content"]
+        .as_array()
+        .ok_or_else(|| ApiError::malformed("Field content not found"))?;
+    let mut text = String::new();
+    for block in blocks.iter().filter(|block| block["type"] == block_type) {
+        text.push_str(&extract_field(block, &[block_type]).map_err(ApiError::malformed)?);
+    }
+    Ok(text)
+}
+
+#[async_trait]
+impl ChatProvider for AnthropicAi {
+    fn name(&self) -> &str {
+        "Anthropic"
+    }
+
+    fn request_body(&self, conv: &Conversation) -> serde_json::Value {
+        let (system, messages) = to_anthropic_request(conv);
+        let mut payload = json!({
+            "model": self.options.model(&self.model),
+            "max_tokens": self.options.params.max_tokens.unwrap_or(self.max_tokens),
+            "messages": messages,
+        });
+        if let Some(system) = system {
+            payload["system"] = json!(system);
+        }
+        // the Messages API has no frequency or presence penalties
+        if let Some(temperature) = self.options.params.temperature {
+            payload["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = self.options.params.top_p {
+            payload["top_p"] = json!(top_p);
+        }
+        if !self.options.params.stop.is_empty() {
+            payload["stop_sequences"] = json!(self.options.params.stop);
+        }
+        payload
+    }
+
+    async fn chat(&self, conv: Conversation) -> Result<ChatResponse> {
+        let payload = self.request_body(&conv);
+        let response = self
+            .options
+            .retry
+            .send("Anthropic API", || {
+                self.options.client
+                    .post(self.endpoint())
+                    .header("Content-Type", "application/json")
+                    .header("x-api-key", &self.api_key)
+                    .header("anthropic-version", ANTHROPIC_VERSION)
+                    .json(&payload)
+                    .send()
+            })
+            .await?;
+
+        let response_json = read_json(response).await?;
+
+        let content = collect_blocks(&response_json, "text")?;
+        let reasoning = collect_blocks(&response_json, "thinking")?;
+        let input_tokens = response_json["usage"]["input_tokens"].as_u64();
+        let output_tokens = response_json["usage"]["output_tokens"].as_u64();
+        let metadata = ResponseMetadata {
+            model: response_json["model"].as_str().map(|s| s.to_string()),
+            finish_reason: response_json["stop_reason"].as_str().map(|s| s.to_string()),
+            usage: Some(Usage {
+                prompt_tokens: input_tokens,
+                completion_tokens: output_tokens,
+                total_tokens: input_tokens.zip(output_tokens).map(|(i, o)| i + o),
+            }),
+        };
+
+        Ok(ChatResponse {
+            message: Message::Assistant {
+                content,
+                reasoning: Some(reasoning).filter(|r| !r.is_empty()),
+                usage: metadata.usage.clone(),
+            },
+            metadata,
+        })
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    #[cfg(test)]
+    use crate::api::test_server::{serve, CannedResponse};
+
+    #[allow(dead_code)]
+    const CHAT_MARKDOWN: &str = r#"# chat.md
+
+---
+### System
+---
+You are a helper assistant
+
+---
+### User
+---
+9.8 and 9.11, which is bigger?
+
+---
+### Assistant
+---
+9.8 is bigger.
+
+---
+### User
+---
+why?
+"#;
+
+    #[allow(dead_code)]
+    const MESSAGES_JSON: &str = r#"{
+        "id": "msg_01",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-test",
+        "content": [
+            {"type": "thinking", "thinking": "compare tenths", "signature": "sig"},
+            {"type": "text", "text": "Because 0.8 is 0.80, "},
+            {"type": "text", "text": "which beats 0.11."}
+        ],
+        "stop_reason": "end_turn",
+        "usage": {"input_tokens": 30, "output_tokens": 12}
+    }"#;
+
+    #[cfg(test)]
+    fn parse_chat_markdown() -> Conversation {
+        use std::io::Write;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(CHAT_MARKDOWN.as_bytes()).unwrap();
+        crate::api::md_paraser::parse_markdown_file(file.path().to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_consecutive_roles_are_merged() {
+        let mut conv = Conversation::new("sys".to_string());
+        conv.set_role_order(crate::api::conversation::RoleOrder::Relaxed);
+        conv.user("first".to_string()).unwrap();
+        conv.user("second".to_string()).unwrap();
+        conv.system("be brief".to_string()).unwrap();
+        conv.assistant("ok".to_string()).unwrap();
+
+        let (system, messages) = to_anthropic_request(&conv);
+        assert_eq!(system.unwrap(), "sys\n\nbe brief");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(messages[0]["content"][1]["text"], "second");
+        assert_eq!(messages[1]["role"], "assistant");
+    }
+
+    #[test]
+    fn test_system_prompt_is_top_level() {
+        let (system, messages) = to_anthropic_request(&parse_chat_markdown());
+        assert_eq!(system.unwrap(), "You are a helper assistant");
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(messages[1]["role"], "assistant");
+        assert_eq!(messages[2]["content"][0]["type"], "text");
+        assert!(messages[2]["content"][0]["text"].as_str().unwrap().contains("why?"));
+    }
+
+    #[test]
+    fn test_request_body() {
+        use crate::api::params::ChatParams;
+
+        let params = ChatParams { model: Some("claude-other".to_string()), stop: vec!["###".to_string()], ..ChatParams::default() };
+        let provider = AnthropicAi::new("secret".to_string(), "claude-test".to_string())
+            .with_options(ProviderOptions { params, ..ProviderOptions::default() });
+        let body = provider.request_body(&parse_chat_markdown());
+        assert_eq!(body["model"], "claude-other");
+        assert_eq!(body["system"], "You are a helper assistant");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(body["stop_sequences"][0], "###");
+        assert_eq!(body["max_tokens"], 2048);
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_error() {
+        use crate::api::retry::RetryPolicy;
+
+        let (base_url, _) = serve(vec![CannedResponse::json(
+            529,
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+        )])
+        .await;
+        let provider = AnthropicAi::new("secret".to_string(), "claude-test".to_string())
+            .with_base_url(base_url)
+            .with_options(ProviderOptions { retry: RetryPolicy { max_attempts: 1, ..RetryPolicy::default() }, ..ProviderOptions::default() });
+
+        let error = provider.chat(parse_chat_markdown()).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ApiError>(),
+            Some(&ApiError::Server { status: 529, message: "Overloaded".to_string() })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_against_stand_in_server() {
+        let (base_url, requests) = serve(vec![CannedResponse::json(200, MESSAGES_JSON)]).await;
+        let provider = AnthropicAi::new("secret".to_string(), "claude-test".to_string()).with_base_url(base_url);
+
+        let response = provider.chat(parse_chat_markdown()).await.unwrap();
+        match response.message {
+            Message::Assistant { content, reasoning, .. } => {
+                assert_eq!(content, "Because 0.8 is 0.80, which beats 0.11.");
+                assert_eq!(reasoning.as_deref(), Some("compare tenths"));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert_eq!(response.metadata.finish_reason.as_deref(), Some("end_turn"));
+        assert_eq!(response.metadata.usage.unwrap().total_tokens, Some(42));
+
+        let requests = requests.await.unwrap();
+        assert_eq!(requests[0].path(), "/v1/messages");
+        assert_eq!(requests[0].header("x-api-key").unwrap(), "secret");
+        assert_eq!(requests[0].header("anthropic-version").unwrap(), ANTHROPIC_VERSION);
+        let body = requests[0].json();
+        assert_eq!(body["system"], "You are a helper assistant");
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+    }
+}
@@ -0,0 +1,660 @@
+use crate::api::params::ChatParams;
+use crate::api::secrets;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use toml_edit::DocumentMut;
+
+// Settings of ai-cli, from three layers, each overriding the ones before it:
+//
+// 1. the user config, `$XDG_CONFIG_HOME/ai-cli/config.toml` (`~/.config/ai-cli/config.toml`)
+// 2. the project config, `.ai-cli.toml` in the working directory or the nearest parent
+// 3. environment variables named after the old `setting.ai` keys, e.g. `AI_CLI_PROVIDER=ollama`
+//
+// provider = "openai"
+// stream = true
+//
+// [openai]
+// base_url = "http://localhost:8000/v1"
+// model = "qwen2.5-7b-instruct"
+//
+// [params]
+// temperature = 0.7
+// stop = ["###"]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub provider: Option<String>,
+    pub stream: Option<bool>,
+    pub reasoning_style: Option<String>,
+    pub system_prompt: Option<String>,
+    pub params: ChatParams,
+    pub deepseek: DeepseekConfig,
+    pub openai: OpenAiConfig,
+    pub anthropic: AnthropicConfig,
+    pub ollama: OllamaConfig,
+    pub retry: RetryConfig,
+    pub http: HttpConfig,
+    // the chat files chosen with `set`, by absolute directory
+    pub directories: BTreeMap<String, DirectoryState>,
+}
+
+// Each provider section only takes the keys its backend reads, so a misplaced
+// one such as `[deepseek] model` is reported instead of silently ignored.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeepseekConfig {
+    // kept by earlier versions, see `secrets` for where keys are looked up
+    pub api_key: Option<String>,
+    // command printing the API key, e.g. `pass show deepseek`
+    pub key_command: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    pub api_key: Option<String>,
+    pub key_command: Option<String>,
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+    pub auth_header: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnthropicConfig {
+    pub api_key: Option<String>,
+    pub key_command: Option<String>,
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+}
+
+// a local server needs no API key
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OllamaConfig {
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_attempts: Option<u64>,
+    pub base_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+    pub jitter: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub connect_timeout_secs: Option<u64>,
+    pub read_timeout_secs: Option<u64>,
+    pub timeout_secs: Option<u64>,
+    pub proxy: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DirectoryState {
+    pub resume_file: Option<String>,
+    // resume files of single terminal sessions
+    pub sessions: BTreeMap<String, String>,
+}
+
+pub const PROJECT_CONFIG: &str = ".ai-cli.toml";
+pub const ENV_PREFIX: &str = "AI_CLI_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Text,
+    Integer,
+    Float,
+    Bool,
+    // comma separated in `setting.ai` and environment variables
+    List,
+}
+
+// the `setting.ai` keys, which the code still reads settings by, and where they live
+const KEYS: &[(&str, &str, Kind)] = &[
+    ("PROVIDER", "provider", Kind::Text),
+    ("STREAM", "stream", Kind::Bool),
+    ("REASONING_STYLE", "reasoning_style", Kind::Text),
+    ("SYSTEM_PROMPT", "system_prompt", Kind::Text),
+    ("MODEL", "params.model", Kind::Text),
+    ("TEMPERATURE", "params.temperature", Kind::Float),
+    ("TOP_P", "params.top_p", Kind::Float),
+    ("MAX_TOKENS", "params.max_tokens", Kind::Integer),
+    ("FREQUENCY_PENALTY", "params.frequency_penalty", Kind::Float),
+    ("PRESENCE_PENALTY", "params.presence_penalty", Kind::Float),
+    ("STOP", "params.stop", Kind::List),
+    ("DEEPSEEK_API", "deepseek.api_key", Kind::Text),
+    ("DEEPSEEK_KEY_COMMAND", "deepseek.key_command", Kind::Text),
+    ("OPENAI_BASE_URL", "openai.base_url", Kind::Text),
+    ("OPENAI_MODEL", "openai.model", Kind::Text),
+    ("OPENAI_API_KEY", "openai.api_key", Kind::Text),
+    ("OPENAI_AUTH_HEADER", "openai.auth_header", Kind::Text),
+    ("OPENAI_KEY_COMMAND", "openai.key_command", Kind::Text),
+    ("ANTHROPIC_API_KEY", "anthropic.api_key", Kind::Text),
+    ("ANTHROPIC_MODEL", "anthropic.model", Kind::Text),
+    ("ANTHROPIC_BASE_URL", "anthropic.base_url", Kind::Text),
+    ("ANTHROPIC_MAX_TOKENS", "anthropic.max_tokens", Kind::Integer),
+    ("ANTHROPIC_KEY_COMMAND", "anthropic.key_command", Kind::Text),
+    ("OLLAMA_MODEL", "ollama.model", Kind::Text),
+    ("OLLAMA_BASE_URL", "ollama.base_url", Kind::Text),
+    ("RETRY_MAX_ATTEMPTS", "retry.max_attempts", Kind::Integer),
+    ("RETRY_BASE_DELAY_MS", "retry.base_delay_ms", Kind::Integer),
+    ("RETRY_MAX_DELAY_MS", "retry.max_delay_ms", Kind::Integer),
+    ("RETRY_JITTER", "retry.jitter", Kind::Bool),
+    ("HTTP_CONNECT_TIMEOUT_SECS", "http.connect_timeout_secs", Kind::Integer),
+    ("HTTP_READ_TIMEOUT_SECS", "http.read_timeout_secs", Kind::Integer),
+    ("HTTP_TIMEOUT_SECS", "http.timeout_secs", Kind::Integer),
+    ("HTTP_PROXY", "http.proxy", Kind::Text),
+];
+
+fn lookup(key: &str) -> Option<(&'static str, Kind)> {
+    KEYS.iter().find(|(name, _, _)| *name == key).map(|&(_, path, kind)| (path, kind))
+}
+
+// Settings a checked-in project config may not set, as they decide where requests
+// and the API keys with them go, or run a command; only the user config and the
+// environment can.
+fn user_only(path: &str) -> bool {
+    path == "http.proxy" || [".api_key", ".key_command", ".base_url", ".auth_header"].iter().any(|key| path.ends_with(key))
+}
+
+/// Where the TOML path of a setting such as `OPENAI_BASE_URL` is, e.g. `openai.base_url`.
+pub fn config_path(key: &str) -> Option<&'static str> {
+    lookup(key).map(|(path, _)| path)
+}
+
+impl Kind {
+    fn parse(self, text: &str) -> Result<toml::Value, String> {
+        let text = text.trim();
+        match self {
+            Kind::Text => Ok(toml::Value::String(text.to_string())),
+            Kind::Integer => text.parse().map(toml::Value::Integer).map_err(|_| format!("expected a whole number, got '{}'", text)),
+            Kind::Float => text.parse().map(toml::Value::Float).map_err(|_| format!("expected a number, got '{}'", text)),
+            Kind::Bool => text.parse().map(toml::Value::Boolean).map_err(|_| format!("expected true or false, got '{}'", text)),
+            Kind::List => Ok(toml::Value::Array(
+                text.split(',').filter(|item| !item.is_empty()).map(|item| toml::Value::String(item.to_string())).collect(),
+            )),
+        }
+    }
+}
+
+// The config files of the layers; a missing file is an empty layer.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigPaths {
+    pub user: Option<PathBuf>,
+    pub project: Option<PathBuf>,
+}
+
+impl ConfigPaths {
+    pub fn locate() -> ConfigPaths {
+        let project = std::env::current_dir()
+            .ok()
+            .and_then(|dir| dir.ancestors().map(|dir| dir.join(PROJECT_CONFIG)).find(|path| path.is_file()));
+        ConfigPaths { user: user_config_path(), project }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/ai-cli/config.toml`, or the same under `~/.config` (`%APPDATA%` on Windows).
+pub fn user_config_path() -> Option<PathBuf> {
+    let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let dir = var("XDG_CONFIG_HOME")
+        .or_else(|| var("HOME").map(|home| home.join(".config")))
+        .or_else(|| var("APPDATA"))?;
+    Some(dir.join("ai-cli").join("config.toml"))
+}
+
+impl Config {
+    // Merge the layers. A file or variable that is not valid is skipped and described
+    // in `problems`.
+    pub(crate) fn from_layers(
+        paths: &ConfigPaths,
+        env: impl IntoIterator<Item = (String, String)>,
+        problems: &mut Vec<String>,
+    ) -> Config {
+        let mut merged = toml::Table::new();
+        for (path, project) in [(&paths.user, false), (&paths.project, true)] {
+            let Some(path) = path else {
+                continue;
+            };
+            match read_layer(path) {
+                Ok(mut layer) => {
+                    if project {
+                        drop_user_only(&mut layer, path, problems);
+                    }
+                    merge(&mut merged, layer)
+                }
+                Err(e) => problems.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        let mut variables = toml::Table::new();
+        for (name, value) in env {
+            let Some((path, kind)) = name.strip_prefix(ENV_PREFIX).and_then(lookup) else {
+                continue;
+            };
+            match kind.parse(&value) {
+                Ok(value) => insert(&mut variables, path, value),
+                Err(e) => problems.push(format!("{}: {}", name, e)),
+            }
+        }
+        merge(&mut merged, variables);
+        Config::deserialize(merged).unwrap_or_else(|e| {
+            problems.push(e.to_string());
+            Config::default()
+        })
+    }
+
+    /// The `key_command` in the section of a provider, e.g. `[deepseek]`.
+    pub fn key_command(&self, provider: &str) -> Option<&String> {
+        match provider {
+            "deepseek" => self.deepseek.key_command.as_ref(),
+            "openai" => self.openai.key_command.as_ref(),
+            "anthropic" => self.anthropic.key_command.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The `api_key` earlier versions kept in the provider's section.
+    pub fn stored_api_key(&self, provider: &str) -> Option<&String> {
+        match provider {
+            "deepseek" => self.deepseek.api_key.as_ref(),
+            "openai" => self.openai.api_key.as_ref(),
+            "anthropic" => self.anthropic.api_key.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// A setting by its `setting.ai` name, e.g. `OPENAI_BASE_URL`, as text; lists are
+    /// comma separated.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let (path, _) = lookup(key)?;
+        let root = toml::Value::try_from(self).ok()?;
+        let mut value = &root;
+        for part in path.split('.') {
+            value = value.get(part)?;
+        }
+        match value {
+            toml::Value::String(text) => Some(text.clone()),
+            toml::Value::Array(items) => {
+                Some(items.iter().filter_map(|item| item.as_str()).collect::<Vec<_>>().join(","))
+            }
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+fn read_layer(path: &Path) -> Result<toml::Table, String> {
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+    Config::deserialize(table.clone()).map_err(|e| e.message().to_string())?;
+    Ok(table)
+}
+
+// take the settings only the user may choose out of a project layer
+fn drop_user_only(layer: &mut toml::Table, path: &Path, problems: &mut Vec<String>) {
+    let mut dropped = Vec::new();
+    if layer.remove("directories").is_some() {
+        dropped.push("directories");
+    }
+    for &(_, key_path, _) in KEYS.iter().filter(|(_, key_path, _)| user_only(key_path)) {
+        let Some((section, key)) = key_path.split_once('.') else {
+            continue;
+        };
+        if let Some(toml::Value::Table(table)) = layer.get_mut(section) {
+            if table.remove(key).is_some() {
+                dropped.push(key_path);
+            }
+        }
+    }
+    if !dropped.is_empty() {
+        problems.push(format!(
+            "{}: {} can only be set in the user config or the environment",
+            path.display(),
+            dropped.join(", ")
+        ));
+    }
+}
+
+// tables are merged key by key, anything else is replaced
+fn merge(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn insert(table: &mut toml::Table, path: &str, value: toml::Value) {
+    let (parents, last) = match path.rsplit_once('.') {
+        Some((parents, last)) => (parents.split('.').collect(), last),
+        None => (Vec::new(), path),
+    };
+    let mut table = table;
+    for parent in parents {
+        let entry = table.entry(parent).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        match entry {
+            toml::Value::Table(child) => table = child,
+            _ => return,
+        }
+    }
+    table.insert(last.to_string(), value);
+}
+
+static LOADED: Mutex<Option<Config>> = Mutex::new(None);
+
+/// The settings in effect, loaded on first use. Invalid layers are reported on stderr
+/// once and left out.
+pub fn current() -> Config {
+    let mut loaded = LOADED.lock().unwrap();
+    loaded
+        .get_or_insert_with(|| {
+            let mut problems = Vec::new();
+            let config = Config::from_layers(&ConfigPaths::locate(), std::env::vars(), &mut problems);
+            for problem in problems {
+                eprintln!("Ignoring invalid config {}", problem);
+            }
+            config
+        })
+        .clone()
+}
+
+/// Store a value under `keys` in the TOML file at `path`, keeping the comments and
+/// layout of the rest of the file. An existing value is only replaced if `replace`.
+pub fn write_value(path: &Path, keys: &[&str], value: toml::Value, replace: bool) -> Result<(), String> {
+    let (last, parents) = keys.split_last().ok_or("empty key")?;
+    edit_file(path, |document| {
+        let table = table_at(document, parents, path)?;
+        if replace || !table.contains_key(last) {
+            let value: toml_edit::Value = value.to_string().parse().map_err(|e| format!("{}", e))?;
+            table.insert(last, toml_edit::Item::Value(value));
+        }
+        Ok(())
+    })
+}
+
+/// Make `file` the chat file of `session` in `directory` in the TOML file at `path`.
+/// Sessions are kept from the least to the most recently used one, and only the
+/// `keep` most recent ones of a directory are remembered.
+pub fn write_session(path: &Path, directory: &str, session: &str, file: &str, keep: usize) -> Result<(), String> {
+    edit_file(path, |document| {
+        let sessions = table_at(document, &["directories", directory, "sessions"], path)?;
+        sessions.remove(session);
+        sessions.insert(session, toml_edit::value(file));
+        while sessions.len() > keep {
+            let Some(oldest) = sessions.iter().next().map(|(key, _)| key.to_string()) else {
+                break;
+            };
+            sessions.remove(&oldest);
+        }
+        Ok(())
+    })
+}
+
+// Apply `change` to the TOML file at `path`, which need not exist yet.
+fn edit_file(path: &Path, change: impl FnOnce(&mut DocumentMut) -> Result<(), String>) -> Result<(), String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let mut document: DocumentMut = text.parse().map_err(|e| format!("{}: {}", path.display(), e))?;
+    change(&mut document)?;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, document.to_string()).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    *LOADED.lock().unwrap() = None;
+    Ok(())
+}
+
+// the table under `keys`, created as needed
+fn table_at<'a>(document: &'a mut DocumentMut, keys: &[&str], path: &Path) -> Result<&'a mut toml_edit::Table, String> {
+    let mut table = document.as_table_mut();
+    for key in keys {
+        let item = table.entry(key).or_insert_with(|| {
+            let mut child = toml_edit::Table::new();
+            child.set_implicit(true);
+            toml_edit::Item::Table(child)
+        });
+        table = item.as_table_mut().ok_or_else(|| format!("{} in {} is not a table", key, path.display()))?;
+    }
+    Ok(table)
+}
+
+// a value of `setting.ai` that was never filled in
+const PLACEHOLDER: &str = "To_BE_FILLED_BY_PROGRAM";
+
+/// Where `migrate_setting_file` puts the settings.
+pub struct MigrationTargets<'a> {
+    // resume files, kept for `directory`, and the endpoints a project config may not set
+    pub user: &'a Path,
+    pub directory: &'a str,
+    // API keys
+    pub secrets: &'a Path,
+    // everything else
+    pub project: &'a Path,
+}
+
+/// Move the `KEY=VALUE` lines of an old `setting.ai` file into the config. Settings
+/// already in the config win. Returns the keys moved and the lines that were not
+/// understood.
+pub fn migrate_setting_file(contents: &str, to: &MigrationTargets) -> Result<(Vec<String>, Vec<String>), String> {
+    let (user, directory, project) = (to.user, to.directory, to.project);
+    let mut moved = Vec::new();
+    let mut skipped = Vec::new();
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((key, value)) = line.split_once('=').filter(|(_, value)| !value.is_empty() && *value != PLACEHOLDER)
+        else {
+            if !line.ends_with(PLACEHOLDER) && !line.ends_with('=') {
+                skipped.push(line.to_string());
+            }
+            continue;
+        };
+        let text = toml::Value::String(value.to_string());
+        if key == "RESUME_FILE" {
+            write_value(user, &["directories", directory, "resume_file"], text, false)?;
+        } else if let Some(session) = key.strip_prefix("RESUME_FILE_") {
+            write_value(user, &["directories", directory, "sessions", session], text, false)?;
+        } else if let Some(provider) = config_path(key).and_then(|path| path.strip_suffix(".api_key")) {
+            secrets::create_private(to.secrets).map_err(|e| format!("Failed to create {}: {}", to.secrets.display(), e))?;
+            write_value(to.secrets, &[provider], text, false)?;
+        } else if let Some((path, kind)) = lookup(key) {
+            let keys: Vec<&str> = path.split('.').collect();
+            let file = if user_only(path) { user } else { project };
+            match kind.parse(value) {
+                Ok(value) => write_value(file, &keys, value, false)?,
+                Err(_) => {
+                    skipped.push(line.to_string());
+                    continue;
+                }
+            }
+        } else {
+            skipped.push(line.to_string());
+            continue;
+        }
+        moved.push(key.to_string());
+    }
+    Ok((moved, skipped))
+}
+
+/// `setting.ai` contents without the API key lines, to keep as a backup once the
+/// keys are in `secrets.toml`.
+pub fn without_api_keys(contents: &str) -> String {
+    contents
+        .lines()
+        .filter(|line| {
+            let key = line.split_once('=').map_or("", |(key, _)| key);
+            !config_path(key).is_some_and(|path| path.ends_with(".api_key"))
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("config.toml");
+        let project = dir.path().join(PROJECT_CONFIG);
+        fs::write(&user, "provider = \"openai\"\n[openai]\nmodel = \"a\"\napi_key = \"secret\"\n[retry]\nmax_attempts = 5\n").unwrap();
+        fs::write(&project, "[openai]\nmodel = \"b\"\n[params]\ntemperature = 0.5\nstop = [\"x\", \"y\"]\n").unwrap();
+        let paths = ConfigPaths { user: Some(user), project: Some(project) };
+
+        let mut problems = Vec::new();
+        let variables = env(&[("AI_CLI_PROVIDER", "ollama"), ("AI_CLI_SESSION", "7"), ("PATH", "/bin")]);
+        let config = Config::from_layers(&paths, variables, &mut problems);
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(config.get("PROVIDER").as_deref(), Some("ollama"));
+        assert_eq!(config.get("OPENAI_MODEL").as_deref(), Some("b"));
+        assert_eq!(config.get("OPENAI_API_KEY").as_deref(), Some("secret"));
+        assert_eq!(config.get("RETRY_MAX_ATTEMPTS").as_deref(), Some("5"));
+        assert_eq!(config.get("TEMPERATURE").as_deref(), Some("0.5"));
+        assert_eq!(config.get("STOP").as_deref(), Some("x,y"));
+        assert_eq!(config.get("OLLAMA_MODEL"), None);
+        assert_eq!(config.get("NOT_A_SETTING"), None);
+    }
+
+    #[test]
+    fn test_invalid_layers_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("config.toml");
+        let project = dir.path().join(PROJECT_CONFIG);
+        fs::write(&user, "provider = \"ollama\"\n").unwrap();
+        fs::write(&project, "[ollama]\nmodle = \"typo\"\n").unwrap();
+        let paths = ConfigPaths { user: Some(user), project: Some(project.clone()) };
+
+        let mut problems = Vec::new();
+        let config = Config::from_layers(&paths, env(&[("AI_CLI_RETRY_MAX_ATTEMPTS", "many")]), &mut problems);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("modle"), "{}", problems[0]);
+        assert!(problems[1].starts_with("AI_CLI_RETRY_MAX_ATTEMPTS"), "{}", problems[1]);
+        assert_eq!(config.get("PROVIDER").as_deref(), Some("ollama"));
+
+        // keys a backend does not read are reported too
+        fs::write(&project, "[deepseek]\nmodel = \"deepseek-chat\"\n").unwrap();
+        let mut problems = Vec::new();
+        Config::from_layers(&paths, env(&[]), &mut problems);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("unknown field `model`"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn test_project_layer_cannot_redirect() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("config.toml");
+        let project = dir.path().join(PROJECT_CONFIG);
+        fs::write(&user, "[anthropic]\nmodel = \"a\"\n").unwrap();
+        fs::write(
+            &project,
+            "[anthropic]\nmodel = \"b\"\nbase_url = \"http://evil\"\napi_key = \"theirs\"\n[http]\nproxy = \"http://evil\"\n\
+             [directories.\"/home\"]\nresume_file = \"x.md\"\n",
+        )
+        .unwrap();
+        let paths = ConfigPaths { user: Some(user), project: Some(project) };
+
+        let mut problems = Vec::new();
+        let config = Config::from_layers(&paths, Vec::new(), &mut problems);
+        assert_eq!(config.get("ANTHROPIC_MODEL").as_deref(), Some("b"));
+        assert_eq!(config.get("ANTHROPIC_BASE_URL"), None);
+        assert_eq!(config.get("ANTHROPIC_API_KEY"), None);
+        assert_eq!(config.get("HTTP_PROXY"), None);
+        assert!(config.directories.is_empty());
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("directories, anthropic.api_key, anthropic.base_url, http.proxy"), "{}", problems[0]);
+
+        // the environment is the user's own
+        let config = Config::from_layers(&paths, env(&[("AI_CLI_ANTHROPIC_BASE_URL", "http://proxy")]), &mut Vec::new());
+        assert_eq!(config.get("ANTHROPIC_BASE_URL").as_deref(), Some("http://proxy"));
+    }
+
+    #[test]
+    fn test_write_value_keeps_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ai-cli").join("config.toml");
+        write_value(&path, &["deepseek", "api_key"], toml::Value::String("one".to_string()), true).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, format!("# my settings\nprovider = \"deepseek\" # for now\n{}", text)).unwrap();
+
+        write_value(&path, &["deepseek", "api_key"], toml::Value::String("two".to_string()), true).unwrap();
+        write_value(&path, &["deepseek", "api_key"], toml::Value::String("three".to_string()), false).unwrap();
+        write_value(&path, &["directories", "/home/me/notes", "resume_file"], toml::Value::String("a.md".to_string()), true)
+            .unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# my settings\nprovider = \"deepseek\" # for now\n"), "{}", text);
+        assert!(text.contains("[directories.\"/home/me/notes\"]\nresume_file = \"a.md\""), "{}", text);
+
+        let config = Config::from_layers(&ConfigPaths { user: Some(path), project: None }, Vec::new(), &mut Vec::new());
+        assert_eq!(config.get("DEEPSEEK_API").as_deref(), Some("two"));
+        assert_eq!(config.directories["/home/me/notes"].resume_file.as_deref(), Some("a.md"));
+    }
+
+    #[test]
+    fn test_write_session_keeps_recent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        for (session, file) in [("a", "1.md"), ("b", "2.md"), ("c", "3.md"), ("a", "4.md"), ("d", "5.md")] {
+            write_session(&path, "/work", session, file, 3).unwrap();
+        }
+        let config = Config::from_layers(&ConfigPaths { user: Some(path), project: None }, Vec::new(), &mut Vec::new());
+        let sessions = &config.directories["/work"].sessions;
+        assert_eq!(sessions.len(), 3);
+        assert_eq!((sessions["a"].as_str(), sessions["d"].as_str()), ("4.md", "5.md"));
+        assert!(!sessions.contains_key("b"));
+    }
+
+    #[test]
+    fn test_migrate_setting_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("config.toml");
+        let secrets = dir.path().join("secrets.toml");
+        let project = dir.path().join(PROJECT_CONFIG);
+        fs::write(&project, "provider = \"openai\"\n").unwrap();
+        let setting = "DEEPSEEK_API=sk-1\nRESUME_FILE=chat.md\nRESUME_FILE_42=other.md\nPROVIDER=ollama\nOLLAMA_MODEL=llama3.2\n\
+                       OLLAMA_BASE_URL=http://gpu:11434\nSTOP=a,b\nTEMPERATURE=warm\nOPENAI_API_KEY=To_BE_FILLED_BY_PROGRAM\nUNKNOWN=1\n";
+
+        let targets = MigrationTargets { user: &user, directory: "/work", secrets: &secrets, project: &project };
+        let (moved, skipped) = migrate_setting_file(setting, &targets).unwrap();
+        assert_eq!(moved, ["DEEPSEEK_API", "RESUME_FILE", "RESUME_FILE_42", "PROVIDER", "OLLAMA_MODEL", "OLLAMA_BASE_URL", "STOP"]);
+        assert_eq!(skipped, ["TEMPERATURE=warm", "UNKNOWN=1"]);
+
+        let paths = ConfigPaths { user: Some(user.clone()), project: Some(project.clone()) };
+        let config = Config::from_layers(&paths, Vec::new(), &mut Vec::new());
+        // the project config already chose a provider
+        assert_eq!(config.get("PROVIDER").as_deref(), Some("openai"));
+        assert_eq!(config.get("OLLAMA_MODEL").as_deref(), Some("llama3.2"));
+        assert_eq!(config.get("OLLAMA_BASE_URL").as_deref(), Some("http://gpu:11434"));
+        assert!(fs::read_to_string(&user).unwrap().contains("http://gpu:11434"));
+        assert_eq!(config.params.stop, ["a", "b"]);
+        assert_eq!(config.directories["/work"].sessions["42"], "other.md");
+        assert_eq!(fs::read_to_string(&secrets).unwrap(), "deepseek = \"sk-1\"\n");
+        assert!(!fs::read_to_string(&user).unwrap().contains("sk-1"));
+        assert!(!fs::read_to_string(&project).unwrap().contains("sk-1"));
+
+        let backup = without_api_keys(setting);
+        assert!(!backup.contains("sk-1") && !backup.contains("OPENAI_API_KEY"));
+        assert!(backup.starts_with("RESUME_FILE=chat.md\n") && backup.ends_with("UNKNOWN=1\n"));
+    }
+}
@@ -1,15 +1,162 @@
+use std::fmt;
 use serde_json::json;
+use crate::api::meta::ConversationMeta;
 
-#[derive(Debug, Clone)]
+// token accounting reported by the backend, when available
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    pub total_tokens: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Message {
     System(String),
     User(String),
-    Assistant(String),
+    // only `content` is sent back to the model, reasoning and usage are kept for the reader
+    Assistant {
+        content: String,
+        reasoning: Option<String>,
+        usage: Option<Usage>,
+    },
+}
+
+impl Message {
+    pub fn assistant(content: String) -> Message {
+        Message::Assistant { content, reasoning: None, usage: None }
+    }
+}
+
+impl Usage {
+    // rendered as an html comment so it is invisible in the rendered chat
+    pub fn to_comment(&self) -> String {
+        let mut fields = Vec::new();
+        if let Some(tokens) = self.prompt_tokens {
+            fields.push(format!("prompt_tokens={}", tokens));
+        }
+        if let Some(tokens) = self.completion_tokens {
+            fields.push(format!("completion_tokens={}", tokens));
+        }
+        if let Some(tokens) = self.total_tokens {
+            fields.push(format!("total_tokens={}", tokens));
+        }
+        format!("<!-- usage: {} -->", fields.join(" "))
+    }
+
+    pub fn from_comment(line: &str) -> Option<Usage> {
+        let fields = line.trim().strip_prefix("<!-- usage:")?.strip_suffix("-->")?;
+        let mut usage = Usage::default();
+        for field in fields.split_whitespace() {
+            let (name, value) = field.split_once('=')?;
+            let value = value.parse().ok()?;
+            match name {
+                "prompt_tokens" => usage.prompt_tokens = Some(value),
+                "completion_tokens" => usage.completion_tokens = Some(value),
+                "total_tokens" => usage.total_tokens = Some(value),
+                _ => {}
+            }
+        }
+        Some(usage)
+    }
+}
+
+// sub-sections of an Assistant section in the chat markdown
+pub const REASONING_HEADING: &str = "#### Reasoning";
+pub const ANSWER_HEADING: &str = "#### Answer";
+pub const DETAILS_OPEN: &str = "<details>";
+pub const DETAILS_SUMMARY: &str = "<summary>Reasoning</summary>";
+pub const DETAILS_CLOSE: &str = "</details>";
+
+// How the reasoning trace is laid out in the chat markdown. Both layouts are
+// recognised by the parser whichever one is selected for writing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReasoningStyle {
+    // a collapsed `<details><summary>Reasoning</summary>` html block
+    #[default]
+    Details,
+    // `#### Reasoning` / `#### Answer` sub-sections
+    Section,
 }
 
+impl ReasoningStyle {
+    pub fn from_name(name: &str) -> Option<ReasoningStyle> {
+        match name.trim().to_lowercase().as_str() {
+            "details" => Some(ReasoningStyle::Details),
+            "section" => Some(ReasoningStyle::Section),
+            _ => None,
+        }
+    }
+
+    // text written before the reasoning trace
+    pub fn opening(&self) -> String {
+        match self {
+            ReasoningStyle::Details => format!("{}\n{}\n\n", DETAILS_OPEN, DETAILS_SUMMARY),
+            ReasoningStyle::Section => format!("{}\n\n", REASONING_HEADING),
+        }
+    }
+
+    // text separating the reasoning trace from the answer
+    pub fn closing(&self) -> String {
+        match self {
+            ReasoningStyle::Details => format!("\n\n{}\n\n", DETAILS_CLOSE),
+            ReasoningStyle::Section => format!("\n\n{}\n\n", ANSWER_HEADING),
+        }
+    }
+}
+
+// The chat file a conversation was parsed from, kept so that writing it back leaves
+// everything the user wrote untouched and only appends (or cuts off) messages.
+#[derive(Debug, Clone)]
+struct Source {
+    text: String,
+    // byte offset in `text` where the section of each parsed message starts
+    starts: Vec<usize>,
+    // number of leading messages still as they were parsed
+    kept: usize,
+}
+
+// Which role sequences `Conversation::add_message` accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoleOrder {
+    // System first, then User and Assistant taking turns
+    #[default]
+    Strict,
+    // any sequence, kept as written; used for chat files, which are edited by hand
+    Relaxed,
+}
+
+// A message that does not fit where it was added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversationError {
+    // a System message anywhere but first
+    SystemNotFirst,
+    // a User message right after another one
+    ConsecutiveUser,
+    // an Assistant message not answering a User message
+    AssistantWithoutUser,
+}
+
+impl fmt::Display for ConversationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversationError::SystemNotFirst => write!(f, "a System message can only be the first message"),
+            ConversationError::ConsecutiveUser => write!(f, "a User message must follow an Assistant or System message"),
+            ConversationError::AssistantWithoutUser => write!(f, "an Assistant message must follow a User message"),
+        }
+    }
+}
+
+impl std::error::Error for ConversationError {}
+
 #[derive(Debug, Clone)]
 pub struct Conversation {
     messages: Vec<Message>,
+    reasoning_style: ReasoningStyle,
+    role_order: RoleOrder,
+    // front matter of the chat file, if it has one
+    meta: Option<ConversationMeta>,
+    source: Option<Source>,
 }
 
 impl Conversation {
@@ -17,63 +164,103 @@ impl Conversation {
         self.messages.last()
     }
 
-    pub fn auto_add(&mut self, message: String) {
+    // Picks the role from the last message, so it is only meant for turn-taking
+    // conversations, never for rebuilding one that was written down.
+    pub fn auto_add(&mut self, message: String) -> Result<(), ConversationError> {
         // if the last message is a user message, add the assistant message
         // else add the user message
         match self.get_top_message() {
-            Some(Message::User(_)) => {
-                self.add_message(Message::Assistant(message));
-            }
-            _ => {
-                self.add_message(Message::User(message));
-            }
+            Some(Message::User(_)) => self.add_message(Message::assistant(message)),
+            _ => self.add_message(Message::User(message)),
         }
     }
-    
-    pub fn add_message(&mut self, message: Message) {
-        match message {
-            Message::User(_) => {
-                if let Some(Message::Assistant(_)) | Some(Message::System(_)) = self.get_top_message() {
-                    self.messages.push(message);
-                } else {
-                    panic!("User message must follow an Assistant or System message");
-                }
-            }
-            Message::Assistant(_) => {
-                if let Some(Message::User(_)) = self.get_top_message() {
-                    self.messages.push(message);
-                } else {
-                    panic!("Assistant message must follow a User message");
-                }
-            }
-            Message::System(_) => {
-                panic!("System message can only be the first message");
-            }
+
+    /// Append a message. In `RoleOrder::Strict` a message that breaks the System, User,
+    /// Assistant, User... order is refused and the conversation left unchanged.
+    pub fn add_message(&mut self, message: Message) -> Result<(), ConversationError> {
+        if self.role_order == RoleOrder::Relaxed {
+            self.messages.push(message);
+            return Ok(());
+        }
+        match (&message, self.get_top_message()) {
+            (Message::System(_), _) => return Err(ConversationError::SystemNotFirst),
+            (Message::User(_), Some(Message::User(_))) => return Err(ConversationError::ConsecutiveUser),
+            (Message::Assistant { .. }, Some(Message::User(_))) => {}
+            (Message::Assistant { .. }, _) => return Err(ConversationError::AssistantWithoutUser),
+            _ => {}
         }
+        self.messages.push(message);
+        Ok(())
     }
 
-    pub fn system(&mut self, message: String) -> Result<(), String> {
-        let system_message = Message::System(message);
-        self.add_message(system_message);
-        Ok(())
+    pub fn system(&mut self, message: String) -> Result<(), ConversationError> {
+        self.add_message(Message::System(message))
     }
 
-    pub fn assistant(&mut self, message: String) -> Result<(), String> {
-        let assistant_message = Message::Assistant(message);
-        self.add_message(assistant_message);
-        Ok(())
+    pub fn assistant(&mut self, message: String) -> Result<(), ConversationError> {
+        self.add_message(Message::assistant(message))
     }
 
-    pub fn user(&mut self, message: String) -> Result<(), String> {
-        let user_message = Message::User(message);
-        self.add_message(user_message);
-        Ok(())
+    pub fn user(&mut self, message: String) -> Result<(), ConversationError> {
+        self.add_message(Message::User(message))
     }
 
-    pub fn new(sys_message: Message) -> Conversation {
+    /// A conversation holding only the System prompt `system`.
+    pub fn new(system: String) -> Conversation {
         Conversation {
-            messages: vec![sys_message],
+            messages: vec![Message::System(system)],
+            reasoning_style: ReasoningStyle::default(),
+            role_order: RoleOrder::default(),
+            meta: None,
+            source: None,
+        }
+    }
+
+    pub fn reasoning_style(&self) -> ReasoningStyle {
+        self.reasoning_style
+    }
+
+    pub fn set_reasoning_style(&mut self, style: ReasoningStyle) {
+        self.reasoning_style = style;
+    }
+
+    pub fn role_order(&self) -> RoleOrder {
+        self.role_order
+    }
+
+    pub fn set_role_order(&mut self, order: RoleOrder) {
+        self.role_order = order;
+    }
+
+    pub fn meta(&self) -> Option<&ConversationMeta> {
+        self.meta.as_ref()
+    }
+
+    pub fn set_meta(&mut self, meta: Option<ConversationMeta>) {
+        self.meta = meta;
+    }
+
+    // remember the file text the messages were parsed from, `starts` holding the byte
+    // offset of each message's section
+    pub(crate) fn set_source(&mut self, text: String, starts: Vec<usize>) {
+        let kept = starts.len().min(self.messages.len());
+        self.source = Some(Source { text, starts, kept });
+    }
+
+    /// Remove the last message, e.g. to retry a reply. The System message stays.
+    pub fn pop_message(&mut self) -> Option<Message> {
+        if self.messages.len() <= 1 {
+            return None;
+        }
+        let message = self.messages.pop();
+        if let Some(source) = self.source.as_mut() {
+            source.kept = source.kept.min(self.messages.len());
         }
+        message
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
     }
 
     pub fn to_messages(&self) -> Vec<serde_json::Value> {
@@ -87,26 +274,106 @@ impl Conversation {
                     "content": text,
                     "role": "user"
                 }),
-                Message::Assistant(text) => json!({
-                    "content": text,
+                Message::Assistant { content, .. } => json!({
+                    "content": content,
                     "role": "assistant"
                 })
             }
         }).collect()
     }
 
+    /// Like `to_messages`, but consecutive messages of the same role are joined into one,
+    /// for backends that require the roles to alternate.
+    pub fn to_merged_messages(&self) -> Vec<serde_json::Value> {
+        let mut merged: Vec<serde_json::Value> = Vec::new();
+        for message in self.to_messages() {
+            match merged.last_mut() {
+                Some(last) if last["role"] == message["role"] => {
+                    let previous = last["content"].as_str().unwrap_or_default();
+                    let content = format!("{}\n\n{}", previous, message["content"].as_str().unwrap_or_default());
+                    last["content"] = json!(content);
+                }
+                _ => merged.push(message),
+            }
+        }
+        merged
+    }
+
+    // A conversation parsed from a file is written back as that file, cut off after
+    // the messages still kept, followed by the messages added since. Otherwise the
+    // whole file is generated with `filename` as its title.
     pub fn to_markdown(&self, filename:String) -> String {
         let mut markdown = String::new();
-        markdown.push_str(format!("# {}\n\n", filename).as_str());
-        for message in &self.messages {
-            match message {
-                Message::System(text) => markdown.push_str(&format!("---\n### System\n---\n{}\n\n", text)),
-                Message::User(text) => markdown.push_str(&format!("---\n### User\n---\n{}\n\n", text)),
-                Message::Assistant(text) => markdown.push_str(&format!("---\n### Assistant\n---\n{}\n\n", text)),
+        let new_messages = match &self.source {
+            Some(source) => {
+                match source.starts.get(source.kept) {
+                    Some(&start) => markdown.push_str(&source.text[..start]),
+                    None => markdown.push_str(&source.text),
+                }
+                if self.messages.len() > source.kept {
+                    end_with_blank_line(&mut markdown, self.line_ending());
+                }
+                &self.messages[source.kept..]
+            }
+            None => {
+                if let Some(meta) = &self.meta {
+                    markdown.push_str(&meta.to_markdown());
+                }
+                markdown.push_str(format!("# {}\n\n", filename).as_str());
+                &self.messages[..]
             }
+        };
+        let mut sections = String::new();
+        for message in new_messages {
+            self.push_section(&mut sections, message);
         }
+        markdown.push_str(&with_line_ending(&sections, self.line_ending()));
         markdown
     }
+
+    /// The line ending of the file the conversation was parsed from, used for the
+    /// sections added to it so a CRLF file does not end up with mixed line endings.
+    pub fn line_ending(&self) -> &'static str {
+        match &self.source {
+            Some(source) if source.text.contains("\r\n") => "\r\n",
+            _ => "\n",
+        }
+    }
+
+    fn push_section(&self, markdown: &mut String, message: &Message) {
+        match message {
+            Message::System(text) => markdown.push_str(&format!("---\n### System\n---\n{}\n\n", text)),
+            Message::User(text) => markdown.push_str(&format!("---\n### User\n---\n{}\n\n", text)),
+            Message::Assistant { content, reasoning, usage } => {
+                markdown.push_str("---\n### Assistant\n---\n");
+                if let Some(reasoning) = reasoning {
+                    markdown.push_str(&self.reasoning_style.opening());
+                    markdown.push_str(reasoning.trim());
+                    markdown.push_str(&self.reasoning_style.closing());
+                }
+                markdown.push_str(&format!("{}\n\n", content));
+                if let Some(usage) = usage {
+                    markdown.push_str(&format!("{}\n\n", usage.to_comment()));
+                }
+            }
+        }
+    }
+}
+
+// a section delimiter appended to `markdown` needs a blank line before it
+pub(crate) fn end_with_blank_line(markdown: &mut String, line_ending: &str) {
+    let blank_line = line_ending.repeat(2);
+    while !markdown.is_empty() && !markdown.ends_with(&blank_line) {
+        markdown.push_str(line_ending);
+    }
+}
+
+// `text` generated with `\n` line endings, converted to `line_ending`
+pub(crate) fn with_line_ending(text: &str, line_ending: &str) -> String {
+    if line_ending == "\n" {
+        return text.to_string();
+    }
+    text.replace("\r\n", "\n").replace('\n', line_ending)
 }
 
 mod test {
@@ -115,18 +382,77 @@ mod test {
 
     #[test]
     fn test_conversation() {
-        let sys_message = Message::System("You are a helpful assistant".to_string());
-        let conversation = Conversation::new(sys_message);
+        let conversation = Conversation::new("You are a helpful assistant".to_string());
         assert_eq!(conversation.messages.len(), 1);
     }
 
     #[test]
     fn test_conversation_messages() {
-        let sys_message = Message::System("You are a helpful assistant".to_string());
-        let conversation = Conversation::new(sys_message);
+        let conversation = Conversation::new("You are a helpful assistant".to_string());
         let messages = conversation.to_messages();
         assert_eq!(messages.len(), 1);
         assert_eq!(messages[0]["role"], "system");
         assert_eq!(messages[0]["content"], "You are a helpful assistant");
     }
+
+    #[test]
+    fn test_reasoning_is_not_sent() {
+        let mut conversation = Conversation::new("You are a helpful assistant".to_string());
+        conversation.user("hi".to_string()).unwrap();
+        conversation.add_message(Message::Assistant {
+            content: "hello".to_string(),
+            reasoning: Some("the user greets me".to_string()),
+            usage: Some(Usage { prompt_tokens: Some(5), completion_tokens: Some(7), total_tokens: Some(12) }),
+        }).unwrap();
+        let messages = conversation.to_messages();
+        assert_eq!(messages[2]["content"], "hello");
+
+        let markdown = conversation.to_markdown("chat.md".to_string());
+        assert!(markdown.contains("<details>\n<summary>Reasoning</summary>\n\nthe user greets me\n\n</details>\n\nhello\n\n"));
+        assert!(markdown.contains("<!-- usage: prompt_tokens=5 completion_tokens=7 total_tokens=12 -->"));
+
+        conversation.set_reasoning_style(ReasoningStyle::Section);
+        let markdown = conversation.to_markdown("chat.md".to_string());
+        assert!(markdown.contains("#### Reasoning\n\nthe user greets me\n\n#### Answer\n\nhello\n\n"));
+    }
+
+    #[test]
+    fn test_usage_comment_round_trip() {
+        let usage = Usage { prompt_tokens: None, completion_tokens: Some(814), total_tokens: Some(857) };
+        assert_eq!(Usage::from_comment(&usage.to_comment()), Some(usage));
+        assert_eq!(Usage::from_comment("not a usage line"), None);
+    }
+
+    #[test]
+    fn test_ordering_errors() {
+        let mut conversation = Conversation::new("sys".to_string());
+        assert_eq!(conversation.assistant("hello".to_string()), Err(ConversationError::AssistantWithoutUser));
+        assert_eq!(conversation.system("again".to_string()), Err(ConversationError::SystemNotFirst));
+        conversation.user("hi".to_string()).unwrap();
+        assert_eq!(conversation.user("hi again".to_string()), Err(ConversationError::ConsecutiveUser));
+        assert_eq!(conversation.messages().len(), 2);
+
+        conversation.auto_add("hello".to_string()).unwrap();
+        conversation.auto_add("bye".to_string()).unwrap();
+        assert_eq!(conversation.messages()[2], Message::assistant("hello".to_string()));
+        assert_eq!(conversation.messages()[3], Message::User("bye".to_string()));
+    }
+
+    #[test]
+    fn test_relaxed_order() {
+        let mut conversation = Conversation::new("sys".to_string());
+        conversation.set_role_order(RoleOrder::Relaxed);
+        conversation.user("first".to_string()).unwrap();
+        conversation.user("second".to_string()).unwrap();
+        conversation.system("be brief".to_string()).unwrap();
+        conversation.assistant("ok".to_string()).unwrap();
+        conversation.assistant("done".to_string()).unwrap();
+        assert_eq!(conversation.to_messages().len(), 6);
+
+        let merged = conversation.to_merged_messages();
+        let roles: Vec<_> = merged.iter().map(|m| m["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, ["system", "user", "system", "assistant"]);
+        assert_eq!(merged[1]["content"], "first\n\nsecond");
+        assert_eq!(merged[3]["content"], "ok\n\ndone");
+    }
 }
@@ -0,0 +1,164 @@
+use std::fmt;
+
+// What went wrong talking to a chat backend, classified from the HTTP status and
+// the provider's `error` object so the CLI can tell the user what to do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    // bad or missing API key (401/403)
+    Auth { message: String },
+    // out of credit or over the plan's limits (402)
+    Quota { message: String },
+    // too many requests (429)
+    RateLimited { message: String },
+    // the conversation does not fit in the model's context window
+    ContextLengthExceeded { message: String },
+    // any other rejected request (400/404/422...)
+    InvalidRequest { status: u16, message: String },
+    // the provider failed or is overloaded (5xx)
+    Server { status: u16, message: String },
+    // the reply could not be understood
+    MalformedResponse { message: String },
+}
+
+impl ApiError {
+    /// Classify a failed response from its status code and body.
+    pub fn from_response(status: u16, body: &str) -> ApiError {
+        let (kind, message) = error_details(body);
+        let message = message.unwrap_or_else(|| {
+            if body.trim().is_empty() {
+                format!("HTTP status {}", status)
+            } else {
+                body.trim().to_string()
+            }
+        });
+        let lower = format!("{} {}", kind.unwrap_or_default(), message).to_lowercase();
+
+        if lower.contains("context_length")
+            || lower.contains("context length")
+            || lower.contains("maximum context")
+            || lower.contains("prompt is too long")
+        {
+            ApiError::ContextLengthExceeded { message }
+        } else if status == 401 || status == 403 || lower.contains("authentication") || lower.contains("invalid_api_key") {
+            ApiError::Auth { message }
+        } else if status == 402 || lower.contains("insufficient") || lower.contains("quota") || lower.contains("credit balance") {
+            ApiError::Quota { message }
+        } else if status == 429 || lower.contains("rate_limit") || lower.contains("rate limit") {
+            ApiError::RateLimited { message }
+        } else if status >= 500 || lower.contains("overloaded") {
+            ApiError::Server { status, message }
+        } else {
+            ApiError::InvalidRequest { status, message }
+        }
+    }
+
+    pub fn malformed(message: impl fmt::Display) -> ApiError {
+        ApiError::MalformedResponse { message: message.to_string() }
+    }
+
+    /// What the user can do about it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ApiError::Auth { .. } => "Check the API key of the selected provider: its `<PROVIDER>_API_KEY` variable, `key_command` or `ai-cli key <api_key>` for Deepseek.",
+            ApiError::Quota { .. } => "Top up the account balance or check the usage limits of your plan.",
+            ApiError::RateLimited { .. } => "Wait a moment before trying again, or send requests less often.",
+            ApiError::ContextLengthExceeded { .. } => {
+                "The conversation is too long for the model, start a new chat file or remove earlier messages."
+            }
+            ApiError::InvalidRequest { .. } => "Check the model name and the request settings in the config.",
+            ApiError::Server { .. } => "The provider is having trouble, try again later.",
+            ApiError::MalformedResponse { .. } => {
+                "The server did not answer like a chat API, check the provider and base url in the config."
+            }
+        }
+    }
+}
+
+// Pull `(type, message)` out of the error shapes used by the supported providers:
+// `{"error": {"message", "type"}}` (OpenAI, Deepseek), `{"type": "error", "error": {...}}`
+// (Anthropic) and `{"error": "..."}` (Ollama).
+fn error_details(body: &str) -> (Option<String>, Option<String>) {
+    let json: serde_json::Value = match serde_json::from_str(body) {
+        Ok(json) => json,
+        Err(_) => return (None, None),
+    };
+    let error = &json["error"];
+    if let Some(message) = error.as_str() {
+        return (None, Some(message.to_string()));
+    }
+    let kind = error["type"].as_str().or_else(|| error["code"].as_str()).map(|s| s.to_string());
+    let message = error["message"].as_str().map(|s| s.to_string());
+    (kind, message)
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Auth { message } => write!(f, "Authentication failed: {}", message),
+            ApiError::Quota { message } => write!(f, "Quota exceeded: {}", message),
+            ApiError::RateLimited { message } => write!(f, "Rate limited: {}", message),
+            ApiError::ContextLengthExceeded { message } => write!(f, "Context length exceeded: {}", message),
+            ApiError::InvalidRequest { status, message } => write!(f, "Request rejected ({}): {}", status, message),
+            ApiError::Server { status, message } => write!(f, "Server error ({}): {}", status, message),
+            ApiError::MalformedResponse { message } => write!(f, "Malformed response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+// Turn a non-2xx response into an `ApiError`, passing successful ones through.
+pub(crate) async fn check_response(response: reqwest::Response) -> Result<reqwest::Response, ApiError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(ApiError::from_response(status.as_u16(), &body))
+}
+
+// Read a JSON body, treating unparsable replies and embedded `error` objects as failures.
+pub(crate) async fn read_json(response: reqwest::Response) -> Result<serde_json::Value, ApiError> {
+    let status = response.status().as_u16();
+    let body = response.text().await.map_err(ApiError::malformed)?;
+    let json: serde_json::Value = serde_json::from_str(&body).map_err(ApiError::malformed)?;
+    if !json["error"].is_null() {
+        return Err(ApiError::from_response(status, &body));
+    }
+    Ok(json)
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_deepseek_errors() {
+        let auth = ApiError::from_response(401, r#"{"error":{"message":"Authentication Fails (no such user)","type":"authentication_error","param":null,"code":"invalid_request_error"}}"#);
+        assert_eq!(auth, ApiError::Auth { message: "Authentication Fails (no such user)".to_string() });
+
+        let balance = ApiError::from_response(402, r#"{"error":{"message":"Insufficient Balance","type":"unknown_error","param":null,"code":"invalid_request_error"}}"#);
+        assert!(matches!(balance, ApiError::Quota { .. }));
+
+        let context = ApiError::from_response(400, r#"{"error":{"message":"This model's maximum context length is 65536 tokens. However, you requested 70000 tokens.","type":"invalid_request_error"}}"#);
+        assert!(matches!(context, ApiError::ContextLengthExceeded { .. }));
+
+        let busy = ApiError::from_response(503, r#"{"error":{"message":"Server overloaded","type":"server_error"}}"#);
+        assert_eq!(busy, ApiError::Server { status: 503, message: "Server overloaded".to_string() });
+    }
+
+    #[test]
+    fn test_other_provider_shapes() {
+        let anthropic = ApiError::from_response(429, r#"{"type":"error","error":{"type":"rate_limit_error","message":"Number of requests has exceeded your rate limit"}}"#);
+        assert!(matches!(anthropic, ApiError::RateLimited { .. }));
+
+        let overloaded = ApiError::from_response(529, r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#);
+        assert!(matches!(overloaded, ApiError::Server { status: 529, .. }));
+
+        let ollama = ApiError::from_response(400, r#"{"error":"invalid options"}"#);
+        assert_eq!(ollama, ApiError::InvalidRequest { status: 400, message: "invalid options".to_string() });
+
+        let html = ApiError::from_response(502, "<html>Bad Gateway</html>");
+        assert_eq!(html, ApiError::Server { status: 502, message: "<html>Bad Gateway</html>".to_string() });
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+use crate::api::setter::read_setting;
+use anyhow::{bail, Context, Result};
+
+// Timeouts and proxy of the HTTP client shared by all requests of a provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpSettings {
+    // time allowed to establish the connection
+    pub connect_timeout: Option<Duration>,
+    // longest silence between two reads of the response, catches stalled streams
+    pub read_timeout: Option<Duration>,
+    // cap on the whole request including the body
+    pub timeout: Option<Duration>,
+    // e.g. `http://proxy:3128`; only http and https proxies are supported
+    pub proxy: Option<String>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(300)),
+            timeout: None,
+            proxy: None,
+        }
+    }
+}
+
+impl HttpSettings {
+    /// Read `HTTP_CONNECT_TIMEOUT_SECS`, `HTTP_READ_TIMEOUT_SECS`, `HTTP_TIMEOUT_SECS`
+    /// and `HTTP_PROXY`. A timeout of `0` disables it.
+    pub fn from_settings() -> Self {
+        let default = HttpSettings::default();
+        let timeout = |key: &str, default: Option<Duration>| match read_setting(key).map(|v| v.trim().parse::<u64>()) {
+            Some(Ok(0)) => None,
+            Some(Ok(secs)) => Some(Duration::from_secs(secs)),
+            _ => default,
+        };
+        HttpSettings {
+            connect_timeout: timeout("HTTP_CONNECT_TIMEOUT_SECS", default.connect_timeout),
+            read_timeout: timeout("HTTP_READ_TIMEOUT_SECS", default.read_timeout),
+            timeout: timeout("HTTP_TIMEOUT_SECS", default.timeout),
+            proxy: read_setting("HTTP_PROXY").filter(|proxy| !proxy.trim().is_empty()),
+        }
+    }
+
+    pub fn build_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            let scheme = proxy.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+            if matches!(scheme.as_deref(), Some(scheme) if scheme != "http" && scheme != "https") {
+                bail!("Unsupported HTTP_PROXY '{}': only http:// and https:// proxies are supported", proxy);
+            }
+            let proxy = reqwest::Proxy::all(proxy).with_context(|| format!("Invalid HTTP_PROXY '{}'", proxy))?;
+            builder = builder.proxy(proxy);
+        }
+        builder.build().context("Failed to build HTTP client")
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_invalid_proxy() {
+        let settings = HttpSettings { proxy: Some("not a url".to_string()), ..HttpSettings::default() };
+        let error = settings.build_client().unwrap_err();
+        assert!(error.to_string().contains("Invalid HTTP_PROXY 'not a url'"));
+    }
+
+    #[test]
+    fn test_unsupported_proxy_scheme() {
+        let settings = HttpSettings { proxy: Some("socks5://127.0.0.1:1080".to_string()), ..HttpSettings::default() };
+        let error = settings.build_client().unwrap_err();
+        assert!(error.to_string().contains("Unsupported HTTP_PROXY 'socks5://127.0.0.1:1080'"));
+
+        let settings = HttpSettings { proxy: Some("http://127.0.0.1:3128".to_string()), ..HttpSettings::default() };
+        assert!(settings.build_client().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout_fails_stalled_request() {
+        // accepts the connection but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            drop(socket);
+        });
+
+        let settings = HttpSettings { read_timeout: Some(Duration::from_millis(200)), ..HttpSettings::default() };
+        let client = settings.build_client().unwrap();
+        let error = client.get(&url).send().await.unwrap_err();
+        assert!(error.is_timeout());
+        server.abort();
+    }
+}
@@ -1,123 +1,314 @@
+use std::fmt;
 use std::fs::File;
 use std::io::{self, Read};
-use crate::api::conversation::{Conversation, Message};
+use crate::api::meta::ConversationMeta;
+use crate::api::conversation::{
+    Conversation, Message, RoleOrder, Usage, ANSWER_HEADING, DETAILS_CLOSE, DETAILS_OPEN, DETAILS_SUMMARY, REASONING_HEADING,
+};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum ReadingType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
     System,
     User,
-    Assistant, 
-    NotReading,
+    Assistant,
 }
 
-// struct to hold the state of the markdown scanner
-#[derive(Debug)]
-struct MarkdownScanner {
-    buffer: String,
-    messages: Vec<Message>,
-    reading_type: ReadingType,
-    reading_lines: Vec<String>,
-}
-
-impl MarkdownScanner {
-    // Create a new MarkdownScanner with initial state
-    fn new() -> MarkdownScanner {
-        MarkdownScanner {
-            buffer: String::new(),
-            messages: Vec::new(),
-            reading_type: ReadingType::NotReading,
-            reading_lines: vec!["".to_string(), "".to_string(), "".to_string()],
+impl Role {
+    fn header(&self) -> &'static str {
+        match self {
+            Role::System => "### System",
+            Role::User => "### User",
+            Role::Assistant => "### Assistant",
         }
     }
+}
 
-    // Scan a line of markdown and update the state accordingly
-    fn scan(&mut self, line: &str) {
-        // Update the reading lines buffer
-        self.reading_lines = vec![self.reading_lines[1].clone(), self.reading_lines[2].clone(), line.to_string()];
-        
-        // Check for section delimiters
-        if self.reading_lines[0].starts_with("---") && self.reading_lines[2].starts_with("---") {
-            match self.reading_lines[1].as_str() {
-                "### System" => {
-                    self.buffer.clear();
-                    self.reading_type = ReadingType::System;
-                }
-                "### User" => {
-                    // delete the last two lines of the buffer string
-                    let mut lines: Vec<&str> = self.buffer.lines().collect();
-                    if lines.len() >= 2 {
-                        lines.pop();
-                        lines.pop();
-                    }
-                    self.buffer = lines.join("\n");
-
-                    self.push_message();
-                    self.reading_type = ReadingType::User;
-                }
-                "### Assistant" => {
-                    // delete the last two lines of the buffer string
-                    let mut lines: Vec<&str> = self.buffer.lines().collect();
-                    if lines.len() >= 2 {
-                        lines.pop();
-                        lines.pop();
-                    }
-                    self.buffer = lines.join("\n");
-
-                    self.push_message();
-                    self.reading_type = ReadingType::Assistant;
-                }
-                _ => {}
+// A `---` / `### Role` / `---` delimited section of a chat file, `line` being the
+// 1-based file line of its `### Role` header and `start` the byte offset of its
+// opening `---` in the text it was parsed from.
+#[derive(Debug)]
+struct Section {
+    role: Role,
+    line: usize,
+    start: usize,
+    body: String,
+}
+
+// Why a chat file could not be read into a conversation.
+#[derive(Debug)]
+pub enum ParseError {
+    Io { path: String, source: io::Error },
+    // `line` is 1-based, `text` the content of that line
+    Syntax { path: String, line: usize, text: String, message: String },
+}
+
+impl ParseError {
+    /// Multi-line report pointing at the offending line, like a compiler diagnostic.
+    pub fn diagnostic(&self) -> String {
+        match self {
+            ParseError::Io { .. } => format!("error: {}", self),
+            ParseError::Syntax { path, line, text, message } => {
+                let gutter = " ".repeat(line.to_string().len());
+                format!(
+                    "error: {}\n{} --> {}:{}\n{} |\n{} | {}\n{} |",
+                    message, gutter, path, line, gutter, line, text, gutter
+                )
             }
-        } else if self.reading_type != ReadingType::NotReading {
-            // Append the line to the buffer if currently reading a section
-            self.buffer.push_str(line);
-            self.buffer.push('\n');
         }
     }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io { path, source } => write!(f, "Failed to read {}: {}", path, source),
+            ParseError::Syntax { path, line, message, .. } => write!(f, "{}:{}: {}", path, line, message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io { source, .. } => Some(source),
+            ParseError::Syntax { .. } => None,
+        }
+    }
+}
 
-    // Push the current buffer as a message and clear the buffer
-    fn push_message(&mut self) {
-        // Add the buffer content to messages based on the current reading type
-        match self.reading_type {
-            ReadingType::System => self.messages.push(Message::System(self.buffer.clone())),
-            ReadingType::User => self.messages.push(Message::User(self.buffer.clone())),
-            ReadingType::Assistant => self.messages.push(Message::Assistant(self.buffer.clone())),
-            ReadingType::NotReading => {}
+impl Section {
+    fn to_message(&self) -> Message {
+        match self.role {
+            Role::System => Message::System(self.body.clone()),
+            Role::User => Message::User(self.body.clone()),
+            Role::Assistant => parse_assistant_body(&self.body),
         }
-        self.buffer.clear();
     }
+}
 
-    // Finalize the scanning process by pushing any remaining buffer content as a message
-    fn finalize(&mut self) {
-        self.push_message();
+fn role_header(line: &str) -> Option<Role> {
+    match line.trim_end() {
+        "### System" => Some(Role::System),
+        "### User" => Some(Role::User),
+        "### Assistant" => Some(Role::Assistant),
+        _ => None,
     }
+}
 
-    fn to_conversation(&self) -> Conversation {
-        let mut result = Conversation::new(self.messages[0].clone());
-        for message in &self.messages[1..] {
-            match message {
-                Message::System(text) => result.system(text.clone()).unwrap(),
-                Message::User(text) | Message::Assistant(text) => result.auto_add(text.clone()),
+// The role of a section delimiter starting at `lines[i]`, if there is one.
+fn delimiter_at(lines: &[&str], i: usize) -> Option<Role> {
+    if i + 2 >= lines.len() || lines[i].trim_end() != "---" || lines[i + 2].trim_end() != "---" {
+        return None;
+    }
+    role_header(lines[i + 1])
+}
+
+// The fence character and length of a line opening or closing a fenced code block,
+// e.g. ``` or ~~~~, indented by at most three spaces.
+fn fence_marker(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = trimmed.chars().take_while(|c| *c == marker).count();
+    if length < 3 {
+        return None;
+    }
+    Some((marker, length))
+}
+
+fn closes_fence(line: &str, (marker, length): (char, usize)) -> bool {
+    match fence_marker(line) {
+        Some((closing, closing_length)) => {
+            closing == marker && closing_length >= length && line.trim()[closing_length..].trim().is_empty()
+        }
+        None => false,
+    }
+}
+
+// Mark the lines inside fenced code blocks, fences included. A fence that is never
+// closed is not treated as one, so a reply cut off in the middle of a code block
+// cannot swallow the sections after it.
+fn fenced_lines(lines: &[&str]) -> Vec<bool> {
+    let mut fenced = vec![false; lines.len()];
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(fence) = fence_marker(lines[i]) {
+            if let Some(end) = (i + 1..lines.len()).find(|&j| closes_fence(lines[j], fence)) {
+                fenced[i..=end].iter_mut().for_each(|line| *line = true);
+                i = end + 1;
+                continue;
             }
         }
-        result
+        i += 1;
     }
+    fenced
 }
 
-pub fn parse_markdown_file(file_path: &str) -> Result<Conversation, io::Error> {
-    let mut file = File::open(file_path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let mut scanner = MarkdownScanner::new();
-    for line in contents.lines() {
-        scanner.scan(line);
+// Split the text after the front matter into sections. Only delimiters outside fenced
+// code blocks count, so code or quoted transcripts inside a message stay in it.
+// `first_line` is the file line the text starts at.
+fn parse_sections(text: &str, first_line: usize) -> Vec<Section> {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut offsets: Vec<usize> = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        offsets.push(offset);
+        offset += line.len();
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        lines.push(line.strip_suffix('\r').unwrap_or(line));
+    }
+    let fenced = fenced_lines(&lines);
+    let mut sections: Vec<Section> = Vec::new();
+    let mut body: Vec<&str> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(role) = delimiter_at(&lines, i).filter(|_| !fenced[i]) {
+            if let Some(section) = sections.last_mut() {
+                section.body = join_body(&body);
+            }
+            body.clear();
+            sections.push(Section { role, line: first_line + i + 1, start: offsets[i], body: String::new() });
+            i += 3;
+            continue;
+        }
+        body.push(lines[i]);
+        i += 1;
+    }
+    if let Some(section) = sections.last_mut() {
+        section.body = join_body(&body);
+    }
+    sections
+}
+
+// the blank lines separating a section from the next delimiter are not part of it
+fn join_body(lines: &[&str]) -> String {
+    let end = lines.iter().rposition(|line| !line.trim().is_empty()).map_or(0, |pos| pos + 1);
+    lines[..end].join("\n")
+}
+
+// Build the conversation with the roles as written, reporting the line of the first
+// section if it is not the System prompt.
+fn to_conversation(sections: &[Section]) -> Result<Conversation, (usize, String)> {
+    let first = sections.first().ok_or((1, "no `### System` section found".to_string()))?;
+    if first.role != Role::System {
+        return Err((first.line, format!("the first section must be `### System`, found `{}`", first.role.header())));
+    }
+    let mut result = Conversation::new(first.body.clone());
+    result.set_role_order(RoleOrder::Relaxed);
+    for section in &sections[1..] {
+        result.add_message(section.to_message()).map_err(|e| (section.line, e.to_string()))?;
+    }
+    Ok(result)
+}
+
+// Split an Assistant section into the answer, an optional reasoning block (either a
+// `<details><summary>Reasoning</summary>` block or a `#### Reasoning` sub-section) and
+// the trailing usage comment written by `Conversation::to_markdown`. A reasoning block
+// that was never closed (the reply was cut off while streaming) is all reasoning.
+fn parse_assistant_body(body: &str) -> Message {
+    let mut lines: Vec<&str> = body.lines().collect();
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    let usage = lines.last().and_then(|line| Usage::from_comment(line));
+    if usage.is_some() {
+        lines.pop();
+    }
+
+    let reasoning_block = find_reasoning_block(&lines);
+    if reasoning_block.is_none() && usage.is_none() {
+        return Message::assistant(body.to_string());
+    }
+    let (reasoning, content) = match reasoning_block {
+        Some((start, Some(end))) => (Some(lines[start..end].join("\n")), lines[end + 1..].join("\n")),
+        Some((start, None)) => (Some(lines[start..].join("\n")), String::new()),
+        None => (None, lines.join("\n")),
+    };
+    Message::Assistant {
+        content: content.trim_matches('\n').to_string(),
+        reasoning: reasoning.map(|r| r.trim_matches('\n').to_string()),
+        usage,
     }
-    scanner.finalize();
-    Ok(scanner.to_conversation())
+}
+
+// Locate a reasoning block at the top of an Assistant section: the index of its first
+// line and, when it was closed, the index of the closing line.
+fn find_reasoning_block(lines: &[&str]) -> Option<(usize, Option<usize>)> {
+    let mut non_empty = lines.iter().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let (first, first_line) = non_empty.next()?;
+    let first_line = first_line.trim();
+    let (start, closing) = if first_line == REASONING_HEADING {
+        (first + 1, ANSWER_HEADING)
+    } else if first_line == format!("{}{}", DETAILS_OPEN, DETAILS_SUMMARY) {
+        (first + 1, DETAILS_CLOSE)
+    } else if first_line == DETAILS_OPEN {
+        let (summary, summary_line) = non_empty.next()?;
+        if summary_line.trim() != DETAILS_SUMMARY {
+            return None;
+        }
+        (summary + 1, DETAILS_CLOSE)
+    } else {
+        return None;
+    };
+    let end = lines[start..].iter().position(|line| line.trim() == closing).map(|pos| start + pos);
+    Some((start, end))
+}
+
+// Split off a YAML front matter block: a `---` first line, closed by the next `---`
+// line. A file starting straight with `---` / `### System` has none.
+fn split_front_matter(contents: &str) -> (Option<String>, &str) {
+    let rest = match contents.strip_prefix("---\n").or_else(|| contents.strip_prefix("---\r\n")) {
+        Some(rest) if !rest.starts_with("### ") => rest,
+        _ => return (None, contents),
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (Some(rest[..offset].to_string()), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, contents)
+}
+
+// Parse the contents of a chat file; errors carry the 1-based line they refer to.
+fn parse_markdown(contents: &str) -> Result<Conversation, (usize, String)> {
+    let (front_matter, body) = split_front_matter(contents);
+    let meta = match front_matter {
+        Some(raw) => Some(ConversationMeta::parse(&raw).map_err(|e| {
+            // the YAML starts on the line after the opening `---`
+            let line = e.location().map_or(1, |location| location.line() + 1);
+            (line, format!("invalid front matter: {}", e))
+        })?),
+        None => None,
+    };
+    let body_start = contents.len() - body.len();
+    let first_line = contents[..body_start].matches('\n').count() + 1;
+    let sections = parse_sections(body, first_line);
+    let mut conversation = to_conversation(&sections)?;
+    conversation.set_meta(meta);
+    conversation.set_source(contents.to_string(), sections.iter().map(|section| body_start + section.start).collect());
+    Ok(conversation)
+}
+
+pub fn parse_markdown_file(file_path: &str) -> Result<Conversation, ParseError> {
+    let mut contents = String::new();
+    File::open(file_path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|source| ParseError::Io { path: file_path.to_string(), source })?;
+    parse_markdown(&contents).map_err(|(line, message)| ParseError::Syntax {
+        path: file_path.to_string(),
+        line,
+        text: contents.lines().nth(line.saturating_sub(1)).unwrap_or_default().to_string(),
+        message,
+    })
 }
 
 mod test {
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_joining_lines() {
         let string_1 = "Hello";
         let string_2 = "World";
@@ -149,19 +340,267 @@ how can i help you today
 "#;
 
     #[test]
-    fn test_markdown_scanner() {
-        let mut scanner = super::MarkdownScanner::new();
-        for line in TEST_MARKDOWN.lines() {
-            scanner.scan(line);
+    fn test_parse_sections() {
+        let sections = super::parse_sections(TEST_MARKDOWN, 1);
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[1].body, "hi\nhow are you today");
+        assert_eq!(sections[2].line, 16);
+    }
+
+    #[allow(dead_code)]
+    fn scan_messages(markdown: &str) -> Vec<crate::api::conversation::Message> {
+        super::parse_sections(markdown, 1).iter().map(super::Section::to_message).collect()
+    }
+
+    #[test]
+    fn test_delimiters_inside_code_fences() {
+        use crate::api::conversation::Message;
+
+        let markdown = "---\n### System\n---\nsys\n\n---\n### User\n---\nWhat does this transcript mean?\n\n```md\n---\n### User\n---\nhi\n```\n\n---\n### Assistant\n---\n~~~~\n```\n---\n### Assistant\n---\n```\n~~~~\nIt is a chat file.\n";
+        let messages = scan_messages(markdown);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1], Message::User("What does this transcript mean?\n\n```md\n---\n### User\n---\nhi\n```".to_string()));
+        assert_eq!(messages[2], Message::assistant("~~~~\n```\n---\n### Assistant\n---\n```\n~~~~\nIt is a chat file.".to_string()));
+    }
+
+    #[test]
+    fn test_horizontal_rules_and_headings() {
+        use crate::api::conversation::Message;
+
+        let markdown = "---\n### System\n---\nsys\n\n---\n### User\n---\nfirst\n\n---\n\nsecond\n----\n### Users\n---\n## Heading\n---\n### Assistant\n---\nreply\n";
+        let messages = scan_messages(markdown);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1], Message::User("first\n\n---\n\nsecond\n----\n### Users\n---\n## Heading".to_string()));
+        assert_eq!(messages[2], Message::assistant("reply".to_string()));
+    }
+
+    #[test]
+    fn test_unclosed_fence_does_not_swallow_sections() {
+        use crate::api::conversation::Message;
+
+        // a reply interrupted in the middle of a code block
+        let markdown = "---\n### System\n---\nsys\n\n---\n### User\n---\ncode?\n\n---\n### Assistant\n---\n```rust\nfn main() {\n\n---\n### User\n---\nthanks\n";
+        let messages = scan_messages(markdown);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[2], Message::assistant("```rust\nfn main() {".to_string()));
+        assert_eq!(messages[3], Message::User("thanks".to_string()));
+    }
+
+    #[test]
+    fn test_round_trip_is_stable() {
+        use crate::api::conversation::Conversation;
+
+        let mut conversation = Conversation::new("sys".to_string());
+        conversation.user("line one\nline two\n\n```\n---\n### User\n---\n```".to_string()).unwrap();
+        conversation.assistant("answer\n\n---\n\nmore".to_string()).unwrap();
+        let markdown = conversation.to_markdown("chat.md".to_string());
+
+        let reparsed = super::parse_markdown(&markdown).unwrap();
+        assert_eq!(reparsed.messages(), conversation.messages());
+        assert_eq!(reparsed.to_markdown("chat.md".to_string()), markdown);
+    }
+
+    #[test]
+    fn test_reasoning_round_trip() {
+        use crate::api::conversation::{Conversation, Message, ReasoningStyle, Usage};
+
+        let mut conversation = Conversation::new("You are a helper assistant".to_string());
+        conversation.user("9.8 and 9.11, which is bigger?".to_string()).unwrap();
+        conversation.add_message(Message::Assistant {
+            content: "9.8 is bigger.\n\n#### Why\nTenths.".to_string(),
+            reasoning: Some("Compare the tenths.\n\nThen answer.".to_string()),
+            usage: Some(Usage { prompt_tokens: Some(43), completion_tokens: Some(814), total_tokens: Some(857) }),
+        }).unwrap();
+        for style in [ReasoningStyle::Details, ReasoningStyle::Section] {
+            conversation.set_reasoning_style(style);
+            let markdown = conversation.to_markdown("chat.md".to_string());
+            match &scan_messages(&markdown)[2] {
+                Message::Assistant { content, reasoning, usage } => {
+                    assert_eq!(content, "9.8 is bigger.\n\n#### Why\nTenths.");
+                    assert_eq!(reasoning.as_deref(), Some("Compare the tenths.\n\nThen answer."));
+                    assert_eq!(usage.as_ref().unwrap().completion_tokens, Some(814));
+                }
+                other => panic!("unexpected message {:?}", other),
+            }
         }
-        scanner.finalize();
-        print!("{:?}", scanner.messages);
-        assert_eq!(scanner.messages.len(), 3);
+    }
+
+    #[test]
+    fn test_details_block_variants() {
+        use crate::api::conversation::Message;
+
+        let one_line = "---\n### System\n---\nsys\n\n---\n### User\n---\nhi\n\n---\n### Assistant\n---\n<details><summary>Reasoning</summary>\n\nthinking\n\n</details>\n\nanswer\n";
+        match &scan_messages(one_line)[2] {
+            Message::Assistant { content, reasoning, .. } => {
+                assert_eq!(content, "answer");
+                assert_eq!(reasoning.as_deref(), Some("thinking"));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        // cut off while the reasoning was still streaming
+        let unclosed = "---\n### System\n---\nsys\n\n---\n### User\n---\nhi\n\n---\n### Assistant\n---\n<details>\n<summary>Reasoning</summary>\n\nthink";
+        match &scan_messages(unclosed)[2] {
+            Message::Assistant { content, reasoning, .. } => {
+                assert_eq!(content, "");
+                assert_eq!(reasoning.as_deref(), Some("think"));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        // any other details block is part of the answer
+        let other = "---\n### System\n---\nsys\n\n---\n### User\n---\nhi\n\n---\n### Assistant\n---\n<details>\n<summary>Spoiler</summary>\nx\n</details>\n";
+        match &scan_messages(other)[2] {
+            Message::Assistant { content, reasoning, .. } => {
+                assert!(content.contains("<summary>Spoiler</summary>"));
+                assert!(reasoning.is_none());
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let markdown = "---\nmodel: deepseek-chat\nparams:\n  temperature: 0.7\n---\n\n# chat.md\n\n---\n### System\n---\nsys\n";
+        let (front_matter, body) = super::split_front_matter(markdown);
+        assert_eq!(front_matter.as_deref(), Some("model: deepseek-chat\nparams:\n  temperature: 0.7\n"));
+        assert!(body.starts_with("\n# chat.md"));
+        assert_eq!(scan_messages(body).len(), 1);
+
+        // a file starting with the System delimiter has no front matter
+        let markdown = "---\n### System\n---\nsys\n";
+        assert_eq!(super::split_front_matter(markdown), (None, markdown));
+    }
+
+    #[test]
+    fn test_front_matter_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.md");
+        let front_matter = "---\n# picked for speed\nprovider: ollama\nmodel: qwen3\ntags: [rust]\n---\n\n";
+        let markdown = format!("{}# chat.md\n\n---\n### System\n---\nsys\n\n---\n### User\n---\nhi\n", front_matter);
+        std::fs::write(&path, markdown).unwrap();
+
+        let conversation = super::parse_markdown_file(path.to_str().unwrap()).unwrap();
+        let meta = conversation.meta().unwrap();
+        assert_eq!(meta.provider.as_deref(), Some("ollama"));
+        assert_eq!(meta.tags, vec!["rust".to_string()]);
+        assert_eq!(conversation.messages().len(), 2);
+        assert!(conversation.to_markdown("chat.md".to_string()).starts_with(front_matter));
+
+        std::fs::write(&path, "---\ntags: [rust\n---\n\n---\n### System\n---\nsys\n").unwrap();
+        let error = super::parse_markdown_file(path.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("chat.md:3: invalid front matter"));
+    }
+
+    #[test]
+    fn test_lossless_write_back() {
+        use crate::api::conversation::Message;
+
+        let original = "---\ntitle: notes # keep me\n---\n# My own title\n\nSome notes under the title.\n\n\n---\n### System\n---\nsys\n\n\n\n---\n### User\n---\n  indented question   \n\n<!-- a comment -->\n---\n### User-ish heading\n---\nstill the question";
+        let mut conversation = super::parse_markdown(original).unwrap();
+        assert_eq!(conversation.to_markdown("ignored.md".to_string()), original);
+
+        conversation.add_message(Message::assistant("answer".to_string())).unwrap();
+        let written = conversation.to_markdown("ignored.md".to_string());
+        assert_eq!(written, format!("{}\n\n---\n### Assistant\n---\nanswer\n\n", original));
+        assert_eq!(super::parse_markdown(&written).unwrap().messages(), conversation.messages());
+
+        // dropping the reply restores the file, dropping the question cuts it off
+        conversation.pop_message();
+        assert_eq!(conversation.to_markdown("ignored.md".to_string()), original);
+        conversation.pop_message();
+        assert_eq!(
+            conversation.to_markdown("ignored.md".to_string()),
+            original[..original.find("---\n### User\n").unwrap()]
+        );
+        conversation.user("new question".to_string()).unwrap();
+        assert!(conversation.to_markdown("ignored.md".to_string()).ends_with("\n\n\n\n---\n### User\n---\nnew question\n\n"));
+        assert!(conversation.pop_message().is_some());
+        assert!(conversation.pop_message().is_none());
+    }
+
+    #[test]
+    fn test_crlf_file_is_kept() {
+        use crate::api::conversation::Message;
+
+        let original = "# chat\r\n\r\n---\r\n### System\r\n---\r\nsys\r\n\r\n---\r\n### User\r\n---\r\nhi\r\n";
+        let mut conversation = super::parse_markdown(original).unwrap();
+        assert_eq!(conversation.messages()[1], Message::User("hi".to_string()));
+        conversation.pop_message();
+        assert_eq!(conversation.to_markdown("chat".to_string()), "# chat\r\n\r\n---\r\n### System\r\n---\r\nsys\r\n\r\n");
+
+        // appended sections use the file's line ending
+        conversation.user("line one\nline two".to_string()).unwrap();
+        assert_eq!(
+            conversation.to_markdown("chat".to_string()),
+            "# chat\r\n\r\n---\r\n### System\r\n---\r\nsys\r\n\r\n---\r\n### User\r\n---\r\nline one\r\nline two\r\n\r\n"
+        );
+
+        let original = "---\r\nprovider: ollama\r\nmodel: qwen3\r\n---\r\n\r\n---\r\n### System\r\n---\r\nsys\r\n";
+        let conversation = super::parse_markdown(original).unwrap();
+        let meta = conversation.meta().unwrap();
+        assert_eq!((meta.provider.as_deref(), meta.model.as_deref()), (Some("ollama"), Some("qwen3")));
+        assert_eq!(conversation.messages().len(), 1);
+        assert_eq!(conversation.to_markdown("chat".to_string()), original);
+    }
+
+    #[test]
+    fn test_malformed_files() {
+        let error = |markdown: &str| super::parse_markdown(markdown).unwrap_err();
+
+        assert_eq!(error(""), (1, "no `### System` section found".to_string()));
+        assert_eq!(error("# chat.md\n\nnotes only\n"), (1, "no `### System` section found".to_string()));
+        assert_eq!(
+            error("# chat.md\n\n---\n### User\n---\nhi\n"),
+            (4, "the first section must be `### System`, found `### User`".to_string())
+        );
+        // line numbers count the front matter
+        assert_eq!(error("---\ntitle: t\n---\n\n---\n### User\n---\nhi\n").0, 6);
+    }
+
+    #[test]
+    fn test_roles_kept_as_written() {
+        use crate::api::conversation::Message;
+
+        let markdown = "---\n### System\n---\nsys\n\n---\n### Assistant\n---\nhello\n\n---\n### User\n---\nhi\n\n---\n### User\n---\nagain\n\n---\n### System\n---\nbe brief\n";
+        let conversation = super::parse_markdown(markdown).unwrap();
+        assert_eq!(
+            conversation.messages(),
+            [
+                Message::System("sys".to_string()),
+                Message::assistant("hello".to_string()),
+                Message::User("hi".to_string()),
+                Message::User("again".to_string()),
+                Message::System("be brief".to_string()),
+            ]
+        );
+        assert_eq!(conversation.to_markdown("chat.md".to_string()), markdown);
+    }
+
+    #[test]
+    fn test_parse_error_diagnostic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.md");
+        std::fs::write(&path, "# chat.md\n\n---\n### User\n---\nhi\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let error = super::parse_markdown_file(path).unwrap_err();
+        assert_eq!(
+            error.diagnostic(),
+            format!(
+                "error: the first section must be `### System`, found `### User`\n  --> {}:4\n  |\n4 | ### User\n  |",
+                path
+            )
+        );
+
+        let missing = dir.path().join("missing.md");
+        let error = super::parse_markdown_file(missing.to_str().unwrap()).unwrap_err();
+        assert!(matches!(error, super::ParseError::Io { .. }));
     }
 
     #[test]
     fn test_parse_markdown_file() {
-        let conversation = super::parse_markdown_file("chat.md").unwrap();
+        let conversation = super::parse_markdown_file("test.md").unwrap();
         let messages = conversation.to_messages();
         print!("{:?}", messages);
         assert!(!messages.is_empty());
@@ -0,0 +1,178 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use crate::api::conversation::{end_with_blank_line, with_line_ending, Conversation, ReasoningStyle};
+use crate::api::provider::StreamEvent;
+
+// Writes an assistant reply into the chat file while it is being generated, so an
+// editor with the file open shows it growing. After `begin` the file always ends
+// with an open `### Assistant` section, so it stays parseable whenever we stop.
+pub struct MarkdownStream {
+    file: File,
+    style: ReasoningStyle,
+    in_reasoning: bool,
+    // the chat file's own line ending, see `Conversation::line_ending`
+    line_ending: &'static str,
+}
+
+impl MarkdownStream {
+    /// Rewrite `path` with the conversation so far followed by an empty Assistant section.
+    pub fn begin(path: &str, conv: &Conversation, title: String) -> io::Result<MarkdownStream> {
+        let line_ending = conv.line_ending();
+        let mut content = conv.to_markdown(title);
+        end_with_blank_line(&mut content, line_ending);
+        content.push_str(&with_line_ending("---\n### Assistant\n---\n", line_ending));
+
+        // write the new content next to the chat file and swap it in, so a crash
+        // here never leaves a truncated conversation behind. A symlink is followed so
+        // the link stays in place, and the file keeps its permissions.
+        let target = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        let mut tmp_path = target.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, content)?;
+        if let Ok(metadata) = fs::metadata(&target) {
+            fs::set_permissions(&tmp_path, metadata.permissions())?;
+        }
+        fs::rename(&tmp_path, &target)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(MarkdownStream {
+            file,
+            style: conv.reasoning_style(),
+            in_reasoning: false,
+            line_ending,
+        })
+    }
+
+    pub fn append(&mut self, text: &str) -> io::Result<()> {
+        self.file.write_all(with_line_ending(text, self.line_ending).as_bytes())?;
+        self.file.flush()
+    }
+
+    // reasoning deltas open a reasoning block which the first answer delta closes,
+    // laid out the same way `Conversation::to_markdown` does it
+    pub fn push(&mut self, event: &StreamEvent) -> io::Result<()> {
+        match event {
+            StreamEvent::Reasoning(delta) => {
+                if !self.in_reasoning {
+                    self.append(&self.style.opening())?;
+                    self.in_reasoning = true;
+                }
+                self.append(delta)
+            }
+            StreamEvent::Content(delta) => {
+                if self.in_reasoning {
+                    self.append(&self.style.closing())?;
+                    self.in_reasoning = false;
+                }
+                self.append(delta)
+            }
+        }
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_partial_reply_is_parseable() {
+        use crate::api::conversation::Message;
+        use crate::api::md_paraser::parse_markdown_file;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.md");
+        let path = path.to_str().unwrap();
+        let mut conv = Conversation::new("You are a helper assistant".to_string());
+        conv.user("9.8 and 9.11, which is bigger?".to_string()).unwrap();
+
+        let mut stream = MarkdownStream::begin(path, &conv, "chat.md".to_string()).unwrap();
+        stream.push(&StreamEvent::Reasoning("Compare".to_string())).unwrap();
+
+        // the process may die at any point from here on
+        let parsed = parse_markdown_file(path).unwrap();
+        match &parsed.messages()[2] {
+            Message::Assistant { content, reasoning, .. } => {
+                assert_eq!(content, "");
+                assert_eq!(reasoning.as_deref(), Some("Compare"));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        stream.push(&StreamEvent::Reasoning(" tenths.".to_string())).unwrap();
+        stream.push(&StreamEvent::Content("9.8 is".to_string())).unwrap();
+        let parsed = parse_markdown_file(path).unwrap();
+        let messages = parsed.to_messages();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2]["role"], "assistant");
+        assert_eq!(messages[2]["content"], "9.8 is");
+
+        stream.push(&StreamEvent::Content(" bigger.".to_string())).unwrap();
+        let contents = fs::read_to_string(path).unwrap();
+        assert!(contents.ends_with(
+            "### Assistant\n---\n<details>\n<summary>Reasoning</summary>\n\nCompare tenths.\n\n</details>\n\n9.8 is bigger."
+        ));
+    }
+
+    #[test]
+    fn test_streams_after_untouched_file() {
+        use crate::api::md_paraser::parse_markdown_file;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.md");
+        let path = path.to_str().unwrap();
+        let original = "# Notes first\n\nkept as is\n---\n### System\n---\nsys\n\n---\n### User\n---\nhi";
+        fs::write(path, original).unwrap();
+
+        let conv = parse_markdown_file(path).unwrap();
+        let mut stream = MarkdownStream::begin(path, &conv, "chat.md".to_string()).unwrap();
+        stream.push(&StreamEvent::Content("hello".to_string())).unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            format!("{}\n\n---\n### Assistant\n---\nhello", original)
+        );
+        assert_eq!(parse_markdown_file(path).unwrap().messages().len(), 3);
+    }
+
+    #[test]
+    fn test_streams_with_crlf() {
+        use crate::api::md_paraser::parse_markdown_file;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.md");
+        let path = path.to_str().unwrap();
+        let original = "# chat\r\n\r\n---\r\n### System\r\n---\r\nsys\r\n\r\n---\r\n### User\r\n---\r\nhi\r\n";
+        fs::write(path, original).unwrap();
+
+        let conv = parse_markdown_file(path).unwrap();
+        let mut stream = MarkdownStream::begin(path, &conv, "chat.md".to_string()).unwrap();
+        stream.push(&StreamEvent::Content("hello\nthere".to_string())).unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            format!("{}\r\n---\r\n### Assistant\r\n---\r\nhello\r\nthere", original)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keeps_symlink_and_permissions() {
+        use crate::api::md_paraser::parse_markdown_file;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("notes").join("chat.md");
+        fs::create_dir(target.parent().unwrap()).unwrap();
+        fs::write(&target, "---\n### System\n---\nsys\n\n---\n### User\n---\nhi").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.path().join("chat.md");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let link = link.to_str().unwrap();
+
+        let conv = parse_markdown_file(link).unwrap();
+        let mut stream = MarkdownStream::begin(link, &conv, "chat.md".to_string()).unwrap();
+        stream.push(&StreamEvent::Content("hello".to_string())).unwrap();
+        assert!(fs::symlink_metadata(link).unwrap().file_type().is_symlink());
+        assert!(fs::read_to_string(&target).unwrap().ends_with("### Assistant\n---\nhello"));
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+}
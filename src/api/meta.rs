@@ -0,0 +1,143 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::api::params::ChatParams;
+use serde::Deserialize;
+
+// Conversation level metadata from the YAML front matter at the top of a chat file:
+//
+// ---
+// title: Rust questions
+// provider: deepseek
+// model: deepseek-chat
+// params:
+//   temperature: 0.7
+// tags: [rust, learning]
+// created: 2025-03-06T09:30:25Z
+// ---
+//
+// Only the fields below are interpreted; the block itself is kept as written, so
+// comments, key order and any other keys survive a rewrite of the file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ConversationMeta {
+    pub title: Option<String>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub params: ChatParams,
+    pub tags: Vec<String>,
+    pub created: Option<String>,
+    #[serde(skip)]
+    raw: String,
+}
+
+impl ConversationMeta {
+    /// Parse the YAML between the `---` fences.
+    pub fn parse(raw: &str) -> Result<ConversationMeta, serde_yaml::Error> {
+        let mut meta: ConversationMeta = if raw.trim().is_empty() {
+            ConversationMeta::default()
+        } else {
+            serde_yaml::from_str(raw)?
+        };
+        meta.raw = raw.to_string();
+        Ok(meta)
+    }
+
+    /// Front matter for a new chat file, stamped with the current time.
+    pub fn new_chat(title: &str) -> ConversationMeta {
+        let created = format_rfc3339(SystemTime::now());
+        let raw = format!("title: {}\ncreated: {}\ntags: []\n", serde_json::to_string(title).unwrap(), created);
+        ConversationMeta {
+            title: Some(title.to_string()),
+            created: Some(created),
+            raw,
+            ..ConversationMeta::default()
+        }
+    }
+
+    // the YAML as it appeared in the file
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// The front matter block including its fences.
+    pub fn to_markdown(&self) -> String {
+        let mut raw = self.raw.clone();
+        if !raw.is_empty() && !raw.ends_with('\n') {
+            raw.push('\n');
+        }
+        format!("---\n{}---\n\n", raw)
+    }
+
+    /// The request parameters set here; a top-level `model` wins over `params.model`.
+    pub fn chat_params(&self) -> ChatParams {
+        let mut params = self.params.clone();
+        if self.model.is_some() {
+            params.model = self.model.clone();
+        }
+        params
+    }
+}
+
+// UTC timestamp like `2025-03-06T09:30:25Z`
+fn format_rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+
+    // civil date from days since 1970-01-01 in the proleptic Gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_parse_meta() {
+        let raw = "# picked for speed\ntitle: Rust questions\nprovider: ollama\nmodel: qwen3\nparams:\n  temperature: 0.7\ntags: [rust, learning]\ncreated: 2025-03-06T09:30:25Z\nreviewed: false\n";
+        let meta = ConversationMeta::parse(raw).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("Rust questions"));
+        assert_eq!(meta.provider.as_deref(), Some("ollama"));
+        assert_eq!(meta.tags, vec!["rust".to_string(), "learning".to_string()]);
+        assert_eq!(meta.created.as_deref(), Some("2025-03-06T09:30:25Z"));
+        assert_eq!(meta.chat_params().model.as_deref(), Some("qwen3"));
+        assert_eq!(meta.chat_params().temperature, Some(0.7));
+        assert_eq!(meta.to_markdown(), format!("---\n{}---\n\n", raw));
+
+        assert!(ConversationMeta::parse("params:\n  temprature: 0.7\n").is_err());
+        assert!(ConversationMeta::parse("tags: [unclosed\n").is_err());
+    }
+
+    #[test]
+    fn test_new_chat() {
+        let meta = ConversationMeta::new_chat("chat: part 2.md");
+        let parsed = ConversationMeta::parse(meta.raw()).unwrap();
+        assert_eq!(parsed.title.as_deref(), Some("chat: part 2.md"));
+        assert_eq!(parsed.created, meta.created);
+    }
+
+    #[test]
+    fn test_format_rfc3339() {
+        assert_eq!(format_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1445412480);
+        assert_eq!(format_rfc3339(time), "2015-10-21T07:28:00Z");
+        let leap = UNIX_EPOCH + std::time::Duration::from_secs(951782400);
+        assert_eq!(format_rfc3339(leap), "2000-02-29T00:00:00Z");
+    }
+}
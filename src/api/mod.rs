@@ -1,4 +1,20 @@
 pub mod setter;
+pub mod config;
+pub mod secrets;
 pub mod md_paraser;
+pub mod md_writer;
 pub mod conversation;
-pub mod ai;
\ No newline at end of file
+pub mod meta;
+pub mod ai;
+pub mod provider;
+pub mod params;
+pub mod error;
+pub mod retry;
+pub mod http;
+pub mod openai;
+pub mod anthropic;
+pub mod ollama;
+mod sse;
+
+#[cfg(test)]
+mod test_server;
@@ -0,0 +1,308 @@
+use crate::api::conversation::{Conversation, Message, Usage};
+use crate::api::error::ApiError;
+use crate::api::provider::{ChatProvider, ChatResponse, ProviderOptions, ResponseMetadata, StreamEvent};
+use crate::api::setter::read_setting;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+// Local models served by Ollama's `/api/chat` endpoint
+pub struct OllamaAi {
+    base_url: String,
+    model: String,
+    options: ProviderOptions,
+}
+
+const OLLAMA_URL: &str = "http://localhost:11434";
+
+impl OllamaAi {
+    pub fn new(model: String) -> Self {
+        OllamaAi {
+            base_url: OLLAMA_URL.to_string(),
+            model,
+            options: ProviderOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ProviderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Build from the `OLLAMA_MODEL` and `OLLAMA_BASE_URL` settings.
+    pub fn from_settings() -> Result<Self> {
+        let model = read_setting("OLLAMA_MODEL")
+            .filter(|model| !model.is_empty())
+            .ok_or_else(|| anyhow!("ollama.model is not set in the config"))?;
+        let mut provider = OllamaAi::new(model);
+        if let Some(base_url) = read_setting("OLLAMA_BASE_URL").filter(|url| !url.is_empty()) {
+            provider = provider.with_base_url(base_url);
+        }
+        Ok(provider)
+    }
+
+    fn endpoint(&self) -> String {
+        format!("{}/api/chat", self.base_url.trim_end_matches('/'))
+    }
+
+    fn model(&self) -> &str {
+        self.options.model(&self.model)
+    }
+
+    // Ollama takes sampling parameters as model `options`, `num_predict` being max tokens
+    fn options(&self) -> serde_json::Value {
+        let mut options = json!({});
+        self.options.params.apply_to(&mut options);
+        if let Some(max_tokens) = options.as_object_mut().and_then(|o| o.remove("max_tokens")) {
+            options["num_predict"] = max_tokens;
+        }
+        options
+    }
+
+    // Ollama reports problems as `{"error": "..."}`; the most common one is a model
+    // that has not been pulled yet, which deserves a hint on how to fix it
+    fn describe_error(&self, error: anyhow::Error) -> anyhow::Error {
+        match error.downcast_ref::<ApiError>() {
+            Some(ApiError::InvalidRequest { message, .. }) if message.contains("not found") && message.contains("pull") => {
+                anyhow!("Model '{}' is not available locally, run `ollama pull {}` first ({})", self.model(), self.model(), message)
+            }
+            _ => error,
+        }
+    }
+}
+
+// Splits a byte stream into complete newline delimited JSON lines, keeping any
+// trailing partial line until the next chunk arrives.
+#[derive(Debug, Default)]
+pub(crate) struct NdjsonDecoder {
+    buffer: Vec<u8>,
+}
+
+impl NdjsonDecoder {
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    // whatever is left once the stream has ended
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        let rest = String::from_utf8_lossy(&self.buffer).trim().to_string();
+        self.buffer.clear();
+        if rest.is_empty() {
+            None
+        } else {
+            Some(rest)
+        }
+    }
+}
+
+// accumulated state of a streamed reply
+#[derive(Debug, Default)]
+struct OllamaReply {
+    content: String,
+    reasoning: String,
+    metadata: ResponseMetadata,
+    done: bool,
+}
+
+impl OllamaAi {
+    fn apply_line(
+        &self,
+        reply: &mut OllamaReply,
+        line: &str,
+        on_event: &mut (dyn FnMut(StreamEvent) + Send),
+    ) -> Result<()> {
+        let chunk: serde_json::Value = serde_json::from_str(line).map_err(ApiError::malformed)?;
+        if !chunk["error"].is_null() {
+            return Err(self.describe_error(ApiError::from_response(200, line).into()));
+        }
+        // thinking models stream their trace in a separate field
+        if let Some(delta) = chunk["message"]["thinking"].as_str().filter(|d| !d.is_empty()) {
+            reply.reasoning.push_str(delta);
+            on_event(StreamEvent::Reasoning(delta.to_string()));
+        }
+        if let Some(delta) = chunk["message"]["content"].as_str().filter(|d| !d.is_empty()) {
+            reply.content.push_str(delta);
+            on_event(StreamEvent::Content(delta.to_string()));
+        }
+        if chunk["done"].as_bool() == Some(true) {
+            let prompt_tokens = chunk["prompt_eval_count"].as_u64();
+            let completion_tokens = chunk["eval_count"].as_u64();
+            reply.metadata = ResponseMetadata {
+                model: chunk["model"].as_str().map(|s| s.to_string()),
+                finish_reason: chunk["done_reason"].as_str().map(|s| s.to_string()),
+                usage: Some(Usage {
+                    prompt_tokens,
+                    completion_tokens,
+                    total_tokens: prompt_tokens.zip(completion_tokens).map(|(p, c)| p + c),
+                }),
+            };
+            reply.done = true;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChatProvider for OllamaAi {
+    fn name(&self) -> &str {
+        "Ollama"
+    }
+
+    fn request_body(&self, conv: &Conversation) -> serde_json::Value {
+        let mut payload = json!({
+            "model": self.model(),
+            "messages": conv.to_messages(),
+            "stream": true,
+        });
+        let options = self.options();
+        if options.as_object().is_some_and(|o| !o.is_empty()) {
+            payload["options"] = options;
+        }
+        payload
+    }
+
+    async fn chat(&self, conv: Conversation) -> Result<ChatResponse> {
+        self.chat_stream(conv, &mut |_| {}).await
+    }
+
+    async fn chat_stream(
+        &self,
+        conv: Conversation,
+        on_event: &mut (dyn FnMut(StreamEvent) + Send),
+    ) -> Result<ChatResponse> {
+        let payload = self.request_body(&conv);
+        let mut response = self
+            .options
+            .retry
+            .send("Ollama", || self.options.client.post(self.endpoint()).json(&payload).send())
+            .await
+            .map_err(|error| {
+                if error.downcast_ref::<ApiError>().is_some() {
+                    self.describe_error(error)
+                } else {
+                    error.context(format!("Failed to reach Ollama at {}, is `ollama serve` running?", self.base_url))
+                }
+            })?;
+
+        let mut decoder = NdjsonDecoder::default();
+        let mut reply = OllamaReply::default();
+        while let Some(chunk) = response.chunk().await.context("Failed to read Ollama response stream")? {
+            for line in decoder.push(&chunk) {
+                self.apply_line(&mut reply, &line, on_event)?;
+            }
+        }
+        if let Some(line) = decoder.finish() {
+            self.apply_line(&mut reply, &line, on_event)?;
+        }
+        if !reply.done {
+            return Err(ApiError::malformed("Ollama response stream ended before the reply was complete").into());
+        }
+
+        Ok(ChatResponse {
+            message: Message::Assistant {
+                content: reply.content,
+                reasoning: Some(reply.reasoning).filter(|r| !r.is_empty()),
+                usage: reply.metadata.usage.clone(),
+            },
+            metadata: reply.metadata,
+        })
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    #[cfg(test)]
+    use crate::api::test_server::{serve, CannedResponse};
+
+    #[allow(dead_code)]
+    const STREAM_NDJSON: &str = concat!(
+        "{\"model\":\"llama3.2\",\"message\":{\"role\":\"assistant\",\"content\":\"\",\"thinking\":\"tenths\"},\"done\":false}\n",
+        "{\"model\":\"llama3.2\",\"message\":{\"role\":\"assistant\",\"content\":\"9.8 is\"},\"done\":false}\n",
+        "{\"model\":\"llama3.2\",\"message\":{\"role\":\"assistant\",\"content\":\" bigger.\"},\"done\":false}\n",
+        "{\"model\":\"llama3.2\",\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":26,\"eval_count\":5}\n",
+    );
+
+    #[allow(dead_code)]
+    fn sample_conversation() -> Conversation {
+        let mut conv = Conversation::new("You are a helper assistant".to_string());
+        conv.user("9.8 and 9.11, which is bigger?".to_string()).unwrap();
+        conv
+    }
+
+    #[test]
+    fn test_ndjson_decoder_split_lines() {
+        let mut decoder = NdjsonDecoder::default();
+        assert!(decoder.push(b"{\"a\":").is_empty());
+        assert_eq!(decoder.push(b"1}\n{\"b\":2}\n{\"c\""), vec!["{\"a\":1}", "{\"b\":2}"]);
+        assert_eq!(decoder.push(b":3}").len(), 0);
+        assert_eq!(decoder.finish().unwrap(), "{\"c\":3}");
+        assert!(decoder.finish().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chat_streamed_reply() {
+        let (base_url, requests) =
+            serve(vec![CannedResponse::with_content_type(200, "application/x-ndjson", STREAM_NDJSON)]).await;
+        let provider = OllamaAi::new("llama3.2".to_string()).with_base_url(base_url);
+
+        let response = provider.chat(sample_conversation()).await.unwrap();
+        match response.message {
+            Message::Assistant { content, reasoning, .. } => {
+                assert_eq!(content, "9.8 is bigger.");
+                assert_eq!(reasoning.as_deref(), Some("tenths"));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert_eq!(response.metadata.usage.unwrap().total_tokens, Some(31));
+
+        let requests = requests.await.unwrap();
+        assert_eq!(requests[0].path(), "/api/chat");
+        assert_eq!(requests[0].json()["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn test_params_become_options() {
+        use crate::api::params::ChatParams;
+
+        let (base_url, requests) =
+            serve(vec![CannedResponse::with_content_type(200, "application/x-ndjson", STREAM_NDJSON)]).await;
+        let params = ChatParams { model: Some("qwen3".to_string()), temperature: Some(0.2), max_tokens: Some(64), ..ChatParams::default() };
+        let provider = OllamaAi::new("llama3.2".to_string()).with_base_url(base_url).with_options(ProviderOptions { params, ..ProviderOptions::default() });
+
+        provider.chat(sample_conversation()).await.unwrap();
+
+        let body = requests.await.unwrap()[0].json();
+        assert_eq!(body["model"], "qwen3");
+        assert_eq!(body["options"]["temperature"], 0.2);
+        assert_eq!(body["options"]["num_predict"], 64);
+        assert!(body["options"].get("max_tokens").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_missing_model_suggests_pull() {
+        let (base_url, _) = serve(vec![CannedResponse::json(
+            404,
+            r#"{"error":"model \"llama3.2\" not found, try pulling it first"}"#,
+        )])
+        .await;
+        let provider = OllamaAi::new("llama3.2".to_string()).with_base_url(base_url);
+
+        let error = provider.chat(sample_conversation()).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("run `ollama pull llama3.2` first"));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::api::ai::{extract_field, extract_usage};
+use crate::api::conversation::{Conversation, Message};
+use crate::api::error::{read_json, ApiError};
+use crate::api::provider::{ChatProvider, ChatResponse, ProviderOptions, ResponseMetadata};
+use crate::api::secrets;
+use crate::api::setter::read_setting;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+// Any server speaking the OpenAI `/v1/chat/completions` dialect: vLLM, LM Studio,
+// llama.cpp, internal gateways...
+pub struct OpenAiCompatible {
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    auth_header: String,
+    options: ProviderOptions,
+}
+
+const DEFAULT_AUTH_HEADER: &str = "Authorization";
+
+impl OpenAiCompatible {
+    pub fn new(base_url: String, model: String) -> Self {
+        OpenAiCompatible {
+            base_url,
+            model,
+            api_key: None,
+            auth_header: DEFAULT_AUTH_HEADER.to_string(),
+            options: ProviderOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ProviderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: String) -> Self {
+        self.api_key = Some(api_key);
+        self
+    }
+
+    // header carrying the key; `Authorization` gets a `Bearer` prefix, anything else
+    // (e.g. `api-key` for Azure style gateways) receives the raw key
+    pub fn with_auth_header(mut self, header: String) -> Self {
+        self.auth_header = header;
+        self
+    }
+
+    /// Build from the `OPENAI_BASE_URL`, `OPENAI_MODEL` and `OPENAI_AUTH_HEADER`
+    /// settings, with the API key from `secrets::api_key` if there is one.
+    pub fn from_settings() -> Result<Self> {
+        let base_url = read_setting("OPENAI_BASE_URL")
+            .filter(|url| !url.is_empty())
+            .ok_or_else(|| anyhow!("openai.base_url is not set in the config"))?;
+        let model = read_setting("OPENAI_MODEL")
+            .filter(|model| !model.is_empty())
+            .ok_or_else(|| anyhow!("openai.model is not set in the config"))?;
+        let mut provider = OpenAiCompatible::new(base_url, model);
+        if let Some(api_key) = secrets::api_key("openai")? {
+            provider = provider.with_api_key(api_key);
+        }
+        if let Some(header) = read_setting("OPENAI_AUTH_HEADER").filter(|header| !header.is_empty()) {
+            provider = provider.with_auth_header(header);
+        }
+        Ok(provider)
+    }
+
+    // accept both `http://host/v1` and a full `.../chat/completions` url
+    fn endpoint(&self) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        if base.ends_with("/chat/completions") {
+            base.to_string()
+        } else {
+            format!("{}/chat/completions", base)
+        }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for OpenAiCompatible {
+    fn name(&self) -> &str {
+        "OpenAI compatible server"
+    }
+
+    fn request_body(&self, conv: &Conversation) -> serde_json::Value {
+        let mut payload = json!({
+            "messages": conv.to_messages(),
+            "model": self.options.model(&self.model),
+        });
+        self.options.params.apply_to(&mut payload);
+        payload
+    }
+
+    async fn chat(&self, conv: Conversation) -> Result<ChatResponse> {
+        let payload = self.request_body(&conv);
+        let response = self
+            .options
+            .retry
+            .send(self.name(), || {
+                let mut request = self
+                    .options
+                    .client
+                    .post(self.endpoint())
+                    .header("Content-Type", "application/json")
+                    .header("Accept", "application/json");
+                if let Some(api_key) = &self.api_key {
+                    if self.auth_header.eq_ignore_ascii_case(DEFAULT_AUTH_HEADER) {
+                        request = request.header(DEFAULT_AUTH_HEADER, format!("Bearer {}", api_key));
+                    } else {
+                        request = request.header(self.auth_header.as_str(), api_key.as_str());
+                    }
+                }
+                request.json(&payload).send()
+            })
+            .await?;
+
+        let response_json = read_json(response).await?;
+
+        let content = extract_field(&response_json["choices"][0], &["message", "content"]).map_err(ApiError::malformed)?;
+        // vLLM and friends report the trace of reasoning models like Deepseek does
+        let reasoning = extract_field(&response_json["choices"][0], &["message", "reasoning_content"]).ok();
+        let metadata = ResponseMetadata {
+            model: response_json["model"].as_str().map(|s| s.to_string()),
+            finish_reason: response_json["choices"][0]["finish_reason"].as_str().map(|s| s.to_string()),
+            usage: extract_usage(&response_json),
+        };
+
+        Ok(ChatResponse {
+            message: Message::Assistant {
+                content,
+                reasoning: reasoning.filter(|r| !r.is_empty()),
+                usage: metadata.usage.clone(),
+            },
+            metadata,
+        })
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    #[cfg(test)]
+    use crate::api::test_server::{serve, CannedResponse};
+
+    #[allow(dead_code)]
+    const COMPLETION_JSON: &str = r#"{
+        "id": "chatcmpl-1",
+        "object": "chat.completion",
+        "model": "local-model",
+        "choices": [{
+            "index": 0,
+            "finish_reason": "stop",
+            "message": {"role": "assistant", "content": "Hello from the stand-in server"}
+        }],
+        "usage": {"prompt_tokens": 12, "completion_tokens": 6, "total_tokens": 18}
+    }"#;
+
+    #[allow(dead_code)]
+    fn sample_conversation() -> Conversation {
+        let mut conv = Conversation::new("You are a helper assistant".to_string());
+        conv.user("hi".to_string()).unwrap();
+        conv
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let provider = OpenAiCompatible::new("http://localhost:1234/v1/".to_string(), "m".to_string());
+        assert_eq!(provider.endpoint(), "http://localhost:1234/v1/chat/completions");
+        let provider = OpenAiCompatible::new("http://gateway/chat/completions".to_string(), "m".to_string());
+        assert_eq!(provider.endpoint(), "http://gateway/chat/completions");
+    }
+
+    #[tokio::test]
+    async fn test_chat_against_stand_in_server() {
+        let (base_url, requests) = serve(vec![CannedResponse::json(200, COMPLETION_JSON)]).await;
+        let provider = OpenAiCompatible::new(format!("{}/v1", base_url), "local-model".to_string())
+            .with_api_key("secret".to_string());
+
+        let response = provider.chat(sample_conversation()).await.unwrap();
+        match response.message {
+            Message::Assistant { content, reasoning, .. } => {
+                assert_eq!(content, "Hello from the stand-in server");
+                assert!(reasoning.is_none());
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert_eq!(response.metadata.usage.unwrap().completion_tokens, Some(6));
+
+        let requests = requests.await.unwrap();
+        assert_eq!(requests[0].path(), "/v1/chat/completions");
+        assert_eq!(requests[0].header("authorization").unwrap(), "Bearer secret");
+        let body = requests[0].json();
+        assert_eq!(body["model"], "local-model");
+        assert_eq!(body["messages"][1]["role"], "user");
+    }
+
+    #[tokio::test]
+    async fn test_custom_auth_header() {
+        let (base_url, requests) = serve(vec![CannedResponse::json(200, COMPLETION_JSON)]).await;
+        let provider = OpenAiCompatible::new(base_url, "local-model".to_string())
+            .with_api_key("secret".to_string())
+            .with_auth_header("api-key".to_string());
+
+        provider.chat(sample_conversation()).await.unwrap();
+
+        let requests = requests.await.unwrap();
+        assert_eq!(requests[0].header("api-key").unwrap(), "secret");
+        assert!(requests[0].header("authorization").is_none());
+    }
+}
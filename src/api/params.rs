@@ -0,0 +1,114 @@
+use crate::api::config::config_path;
+use crate::api::setter::read_setting;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// Model and sampling parameters of a request. Unset fields are left to the provider's
+// defaults. They are layered: settings, then the chat file's front matter, then the
+// command line flags, each overriding the fields it sets.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatParams {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub frequency_penalty: Option<f64>,
+    pub presence_penalty: Option<f64>,
+    pub stop: Vec<String>,
+}
+
+impl ChatParams {
+    /// Read `MODEL`, `TEMPERATURE`, `TOP_P`, `MAX_TOKENS`, `FREQUENCY_PENALTY`,
+    /// `PRESENCE_PENALTY` and `STOP` (comma separated) from the settings.
+    pub fn from_settings() -> Result<Self> {
+        Ok(ChatParams {
+            model: read_setting("MODEL").filter(|model| !model.trim().is_empty()),
+            temperature: parse_setting("TEMPERATURE")?,
+            top_p: parse_setting("TOP_P")?,
+            max_tokens: parse_setting("MAX_TOKENS")?,
+            frequency_penalty: parse_setting("FREQUENCY_PENALTY")?,
+            presence_penalty: parse_setting("PRESENCE_PENALTY")?,
+            stop: read_setting("STOP")
+                .map(|stop| stop.split(',').map(|s| s.to_string()).filter(|s| !s.is_empty()).collect())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Every field set in `other` replaces the one in `self`.
+    pub fn overlay(mut self, other: ChatParams) -> ChatParams {
+        if other.model.is_some() {
+            self.model = other.model;
+        }
+        if other.temperature.is_some() {
+            self.temperature = other.temperature;
+        }
+        if other.top_p.is_some() {
+            self.top_p = other.top_p;
+        }
+        if other.max_tokens.is_some() {
+            self.max_tokens = other.max_tokens;
+        }
+        if other.frequency_penalty.is_some() {
+            self.frequency_penalty = other.frequency_penalty;
+        }
+        if other.presence_penalty.is_some() {
+            self.presence_penalty = other.presence_penalty;
+        }
+        if !other.stop.is_empty() {
+            self.stop = other.stop;
+        }
+        self
+    }
+
+    // add the sampling parameters that are set to an OpenAI style request body
+    pub(crate) fn apply_to(&self, payload: &mut serde_json::Value) {
+        if let Some(temperature) = self.temperature {
+            payload["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            payload["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            payload["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(penalty) = self.frequency_penalty {
+            payload["frequency_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = self.presence_penalty {
+            payload["presence_penalty"] = json!(penalty);
+        }
+        if !self.stop.is_empty() {
+            payload["stop"] = json!(self.stop);
+        }
+    }
+}
+
+fn parse_setting<T: std::str::FromStr>(key: &str) -> Result<Option<T>> {
+    match read_setting(key).filter(|value| !value.trim().is_empty()) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!("{} in the config must be a number, got '{}'", config_path(key).unwrap_or(key), value)),
+        None => Ok(None),
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_overlay_precedence() {
+        let settings = ChatParams { model: Some("a".to_string()), temperature: Some(1.0), max_tokens: Some(100), ..ChatParams::default() };
+        let front_matter = ChatParams { model: Some("b".to_string()), temperature: Some(0.5), ..ChatParams::default() };
+        let flags = ChatParams { temperature: Some(0.1), ..ChatParams::default() };
+
+        let params = settings.overlay(front_matter).overlay(flags);
+        assert_eq!(params.model.as_deref(), Some("b"));
+        assert_eq!(params.temperature, Some(0.1));
+        assert_eq!(params.max_tokens, Some(100));
+    }
+}
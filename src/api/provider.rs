@@ -0,0 +1,149 @@
+use crate::api::ai::DeepseekAi;
+use crate::api::anthropic::AnthropicAi;
+use crate::api::conversation::{Conversation, Message, Usage};
+use crate::api::http::HttpSettings;
+use crate::api::ollama::OllamaAi;
+use crate::api::openai::OpenAiCompatible;
+use crate::api::params::ChatParams;
+use crate::api::retry::RetryPolicy;
+use crate::api::secrets::api_key;
+use crate::api::setter::{read_provider, read_stream};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+// everything a backend tells us about a reply besides the reply itself
+#[derive(Debug, Clone, Default)]
+pub struct ResponseMetadata {
+    pub model: Option<String>,
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatResponse {
+    pub message: Message,
+    pub metadata: ResponseMetadata,
+}
+
+// a piece of the reply as it arrives from a streaming backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    Reasoning(String),
+    Content(String),
+}
+
+// What every provider is built with besides its own settings.
+#[derive(Debug, Clone)]
+pub struct ProviderOptions {
+    // shared by all requests so connections are reused
+    pub client: reqwest::Client,
+    pub retry: RetryPolicy,
+    // model and sampling parameters; a model given here replaces the provider's own
+    pub params: ChatParams,
+}
+
+impl Default for ProviderOptions {
+    // use the default timeouts instead of a client that can wait forever
+    fn default() -> Self {
+        ProviderOptions {
+            client: HttpSettings::default().build_client().unwrap_or_default(),
+            retry: RetryPolicy::default(),
+            params: ChatParams::default(),
+        }
+    }
+}
+
+impl ProviderOptions {
+    /// The HTTP client and retry policy of the settings, with `params`.
+    pub fn from_settings(params: ChatParams) -> Result<Self> {
+        Ok(ProviderOptions {
+            client: HttpSettings::from_settings().build_client()?,
+            retry: RetryPolicy::from_settings(),
+            params,
+        })
+    }
+
+    pub(crate) fn model<'a>(&'a self, configured: &'a str) -> &'a str {
+        self.params.model.as_deref().unwrap_or(configured)
+    }
+}
+
+/// A chat backend: takes the conversation so far and produces the next assistant message.
+#[async_trait]
+pub trait ChatProvider: Send + Sync {
+    // human readable backend name, used in status output
+    fn name(&self) -> &str;
+
+    // the JSON body `chat_stream` sends for `conv`, also shown by `--dry-run`
+    fn request_body(&self, conv: &Conversation) -> serde_json::Value;
+
+    async fn chat(&self, conv: Conversation) -> Result<ChatResponse>;
+
+    // Like `chat`, but hands every delta to `on_event` as soon as it is received.
+    // Backends without streaming support deliver the whole reply as one event.
+    async fn chat_stream(
+        &self,
+        conv: Conversation,
+        on_event: &mut (dyn FnMut(StreamEvent) + Send),
+    ) -> Result<ChatResponse> {
+        let response = self.chat(conv).await?;
+        emit_whole_message(&response.message, on_event);
+        Ok(response)
+    }
+}
+
+// replay a complete message as stream events, for backends that did not stream it
+pub(crate) fn emit_whole_message(message: &Message, on_event: &mut (dyn FnMut(StreamEvent) + Send)) {
+    if let Message::Assistant { content, reasoning, .. } = message {
+        if let Some(reasoning) = reasoning {
+            on_event(StreamEvent::Reasoning(reasoning.clone()));
+        }
+        on_event(StreamEvent::Content(content.clone()));
+    }
+}
+
+/// Build the provider selected by the `PROVIDER` setting.
+pub fn provider_from_settings(params: ChatParams) -> Result<Box<dyn ChatProvider>> {
+    provider_by_name(&read_provider(), params)
+}
+
+/// The provider a `provider` setting names: trimmed, lower case, `deepseek` if empty.
+pub fn provider_key(name: &str) -> String {
+    match name.trim().to_lowercase() {
+        name if name.is_empty() => "deepseek".to_string(),
+        name => name,
+    }
+}
+
+pub fn provider_by_name(name: &str, params: ChatParams) -> Result<Box<dyn ChatProvider>> {
+    let options = ProviderOptions::from_settings(params)?;
+    match provider_key(name).as_str() {
+        "deepseek" => Ok(Box::new(
+            DeepseekAi::new(api_key("deepseek")?.unwrap_or_default())
+                .with_stream(read_stream())
+                .with_options(options),
+        )),
+        "openai" => Ok(Box::new(OpenAiCompatible::from_settings()?.with_options(options))),
+        "anthropic" => Ok(Box::new(AnthropicAi::from_settings()?.with_options(options))),
+        "ollama" => Ok(Box::new(OllamaAi::from_settings()?.with_options(options))),
+        other => Err(anyhow!("Unknown provider '{}', expected one of: deepseek, openai, anthropic, ollama", other)),
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_unknown_provider() {
+        let result = provider_by_name("nonexistent", ChatParams::default());
+        assert!(result.is_err());
+        assert!(result.err().unwrap().to_string().contains("Unknown provider 'nonexistent'"));
+    }
+
+    #[test]
+    fn test_provider_key() {
+        assert_eq!(provider_key(" Ollama "), "ollama");
+        assert_eq!(provider_key(""), "deepseek");
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::api::error::{check_response, ApiError};
+use crate::api::setter::read_setting;
+use anyhow::Result;
+
+// How often and how patiently a request is retried when the provider is busy
+// (429, 5xx) or the connection fails.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // total number of attempts, 1 disables retrying
+    pub max_attempts: u32,
+    // delay before the first retry, doubled for every further one
+    pub base_delay: Duration,
+    // upper bound for a single delay; a longer `Retry-After` makes us give up instead
+    pub max_delay: Duration,
+    // randomise delays so parallel runs do not retry in lockstep
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1000),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Read `RETRY_MAX_ATTEMPTS`, `RETRY_BASE_DELAY_MS`, `RETRY_MAX_DELAY_MS` and
+    /// `RETRY_JITTER`, falling back to the defaults for missing or invalid values.
+    pub fn from_settings() -> Self {
+        let default = RetryPolicy::default();
+        let number = |key: &str| read_setting(key).and_then(|value| value.trim().parse::<u64>().ok());
+        RetryPolicy {
+            max_attempts: number("RETRY_MAX_ATTEMPTS").map(|n| n.max(1) as u32).unwrap_or(default.max_attempts),
+            base_delay: number("RETRY_BASE_DELAY_MS").map(Duration::from_millis).unwrap_or(default.base_delay),
+            max_delay: number("RETRY_MAX_DELAY_MS").map(Duration::from_millis).unwrap_or(default.max_delay),
+            jitter: read_setting("RETRY_JITTER").map(|value| value.trim() != "false").unwrap_or(default.jitter),
+        }
+    }
+
+    // exponential backoff for the given retry (1 = first retry), or the server's
+    // `Retry-After` when it sent one
+    fn delay_for(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after;
+        }
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        if self.jitter {
+            // somewhere between half and the full delay
+            delay.mul_f64(0.5 + random_fraction() / 2.0)
+        } else {
+            delay
+        }
+    }
+
+    /// Send a request built by `send`, retrying transient failures. Every retry is
+    /// reported on stderr. Non-2xx responses that are not retried become an `ApiError`.
+    pub async fn send<F, Fut>(&self, provider: &str, mut send: F) -> Result<reqwest::Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let (reason, retry_after) = match send().await {
+                Ok(response) => {
+                    let retry_after = parse_retry_after(response.headers());
+                    let error = match check_response(response).await {
+                        Ok(response) => return Ok(response),
+                        Err(error) => error,
+                    };
+                    if !is_retryable(&error) || attempt >= self.max_attempts {
+                        return Err(error.into());
+                    }
+                    if let Some(delay) = retry_after.filter(|delay| *delay > self.max_delay) {
+                        eprintln!("{} asked to retry after {}s, not waiting that long.", provider, delay.as_secs());
+                        return Err(error.into());
+                    }
+                    (error.to_string(), retry_after)
+                }
+                Err(error) => {
+                    if !(error.is_connect() || error.is_timeout()) || attempt >= self.max_attempts {
+                        return Err(anyhow::Error::new(error).context(format!("Failed to send request to {}", provider)));
+                    }
+                    (error.to_string(), None)
+                }
+            };
+            let delay = self.delay_for(attempt, retry_after);
+            eprintln!(
+                "{} request failed ({}), retrying in {:.1}s (attempt {}/{})",
+                provider,
+                reason,
+                delay.as_secs_f64(),
+                attempt + 1,
+                self.max_attempts
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn is_retryable(error: &ApiError) -> bool {
+    matches!(error, ApiError::RateLimited { .. } | ApiError::Server { .. })
+}
+
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date
+fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = parse_http_date(value)?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+// IMF-fixdate, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None;
+    }
+    let day: i64 = parts[1].parse().ok()?;
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+        .iter()
+        .position(|m| *m == parts[2])? as i64
+        + 1;
+    let year: i64 = parts[3].parse().ok()?;
+    let time: Vec<u64> = parts[4].split(':').filter_map(|n| n.parse().ok()).collect();
+    if time.len() != 3 {
+        return None;
+    }
+
+    // days since 1970-01-01 in the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146097 + doe - 719468).ok()?;
+
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + time[0] * 3600 + time[1] * 60 + time[2]))
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    #[cfg(test)]
+    use crate::api::test_server::{serve, CannedResponse};
+
+    #[test]
+    fn test_backoff_without_jitter() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            jitter: false,
+        };
+        assert_eq!(policy.delay_for(1, None), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2, None), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(3, None), Duration::from_millis(300));
+        assert_eq!(policy.delay_for(2, Some(Duration::from_secs(2))), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_jitter_stays_in_range() {
+        let policy = RetryPolicy { base_delay: Duration::from_millis(1000), ..RetryPolicy::default() };
+        for _ in 0..20 {
+            let delay = policy.delay_for(1, None);
+            assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let date = parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(date.duration_since(UNIX_EPOCH).unwrap().as_secs(), 1445412480);
+        assert!(parse_http_date("yesterday").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let (base_url, requests) = serve(vec![
+            CannedResponse::json(429, r#"{"error":{"message":"Rate limit reached","type":"rate_limit_error"}}"#)
+                .header("Retry-After", "0"),
+            CannedResponse::json(503, r#"{"error":{"message":"Server overloaded","type":"server_error"}}"#),
+            CannedResponse::json(200, r#"{"ok":true}"#),
+        ])
+        .await;
+        let policy = RetryPolicy { base_delay: Duration::from_millis(1), ..RetryPolicy::default() };
+        let client = reqwest::Client::new();
+
+        let response = policy.send("test", || client.get(&base_url).send()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(requests.await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let (base_url, _) = serve(vec![
+            CannedResponse::json(503, r#"{"error":{"message":"Server overloaded"}}"#),
+            CannedResponse::json(503, r#"{"error":{"message":"Still overloaded"}}"#),
+        ])
+        .await;
+        let policy = RetryPolicy { max_attempts: 2, base_delay: Duration::from_millis(1), ..RetryPolicy::default() };
+        let client = reqwest::Client::new();
+
+        let error = policy.send("test", || client.get(&base_url).send()).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ApiError>(),
+            Some(&ApiError::Server { status: 503, message: "Still overloaded".to_string() })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let (base_url, requests) = serve(vec![CannedResponse::json(401, r#"{"error":{"message":"bad key"}}"#)]).await;
+        let client = reqwest::Client::new();
+
+        let error = RetryPolicy::default().send("test", || client.get(&base_url).send()).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ApiError>(), Some(ApiError::Auth { .. })));
+        assert_eq!(requests.await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::api::config::{self, Config};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// API keys are looked up for each provider in this order:
+//
+// 1. its environment variable: `DEEPSEEK_API_KEY`, `OPENAI_API_KEY` or `ANTHROPIC_API_KEY`
+// 2. the output of its `key_command` setting, e.g. `key_command = "pass show deepseek"`,
+//    from the user config or `AI_CLI_DEEPSEEK_KEY_COMMAND`; a project config cannot set it
+// 3. the secrets file `secrets.toml` next to the user config, readable only by its owner
+// 4. `api_key` in the config, where earlier versions kept it
+//
+// The secrets file holds one key per provider:
+//
+// deepseek = "sk-..."
+// anthropic = "sk-ant-..."
+
+/// `DEEPSEEK_API_KEY` for `deepseek` and so on.
+pub fn env_var(provider: &str) -> String {
+    format!("{}_API_KEY", provider.to_uppercase())
+}
+
+/// `secrets.toml` in the directory of the user config.
+pub fn secrets_path() -> Option<PathBuf> {
+    Some(config::user_config_path()?.with_file_name("secrets.toml"))
+}
+
+/// The API key of `provider` from the first source that has one.
+pub fn api_key(provider: &str) -> Result<Option<String>> {
+    key_from_config(&config::current(), provider, std::env::var(env_var(provider)).ok(), secrets_path().as_deref())
+}
+
+fn key_from_config(config: &Config, provider: &str, env: Option<String>, secrets: Option<&Path>) -> Result<Option<String>> {
+    lookup(provider, env, config.key_command(provider).cloned(), secrets, config.stored_api_key(provider).cloned())
+}
+
+pub(crate) fn lookup(
+    provider: &str,
+    env: Option<String>,
+    key_command: Option<String>,
+    secrets: Option<&Path>,
+    stored: Option<String>,
+) -> Result<Option<String>> {
+    let present = |key: &String| !key.trim().is_empty();
+    if let Some(key) = env.filter(present) {
+        return Ok(Some(key.trim().to_string()));
+    }
+    if let Some(command) = key_command.filter(present) {
+        return run_key_command(&command).map(Some);
+    }
+    if let Some(path) = secrets {
+        if let Some(key) = read_secrets(path)?.get(provider).and_then(|key| key.as_str()) {
+            return Ok(Some(key.trim().to_string()));
+        }
+    }
+    Ok(stored.filter(present))
+}
+
+// the first line the command prints, e.g. `pass show deepseek`
+fn run_key_command(command: &str) -> Result<String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let output = shell.arg(command).output().with_context(|| format!("Failed to run key_command `{}`", command))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let detail = Some(stderr.trim()).filter(|text| !text.is_empty()).map(|text| format!(": {}", text));
+        return Err(anyhow!("key_command `{}` failed ({}){}", command, output.status, detail.unwrap_or_default()));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.lines().next().map(str::trim).filter(|key| !key.is_empty()) {
+        Some(key) => Ok(key.to_string()),
+        None => Err(anyhow!("key_command `{}` printed no key", command)),
+    }
+}
+
+fn read_secrets(path: &Path) -> Result<toml::Table> {
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(anyhow!(
+                "{} can be read by other users, restrict it with `chmod 600 {}`",
+                path.display(),
+                path.display()
+            ));
+        }
+    }
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    text.parse().with_context(|| format!("Invalid secrets file {}", path.display()))
+}
+
+/// Create `path` readable and writable by its owner only, or restrict it so if it
+/// already exists, as `read_secrets` refuses it otherwise.
+pub fn create_private(path: &Path) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if file.metadata()?.permissions().mode() & 0o077 != 0 {
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    Ok(())
+}
+
+/// Store the API key of `provider` in the secrets file.
+pub fn store_api_key(provider: &str, key: &str) -> Result<PathBuf> {
+    let path = secrets_path().ok_or_else(|| anyhow!("no config directory, set XDG_CONFIG_HOME"))?;
+    write_api_key(&path, provider, key)?;
+    Ok(path)
+}
+
+/// Store the API key of `provider` in the secrets file at `path`.
+pub fn write_api_key(path: &Path, provider: &str, key: &str) -> Result<()> {
+    create_private(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    config::write_value(path, &[provider], toml::Value::String(key.to_string()), true).map_err(|e| anyhow!(e))
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_lookup_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let secrets = dir.path().join("secrets.toml");
+        create_private(&secrets).unwrap();
+        fs::write(&secrets, "deepseek = \"from-file\"\n").unwrap();
+        let some = |key: &str| Some(key.to_string());
+
+        let key = |env, command, stored| lookup("deepseek", env, command, Some(&secrets), stored).unwrap();
+        assert_eq!(key(some("from-env"), some("echo from-command"), some("stored")), some("from-env"));
+        assert_eq!(key(some(" "), some("echo from-command; echo second line"), some("stored")), some("from-command"));
+        assert_eq!(key(None, None, some("stored")), some("from-file"));
+        assert_eq!(lookup("openai", None, None, Some(&secrets), some("stored")).unwrap(), some("stored"));
+        assert_eq!(lookup("openai", None, None, None, None).unwrap(), None);
+
+        assert!(lookup("deepseek", None, some("exit 3"), None, None).is_err());
+        assert!(lookup("deepseek", None, some("true"), None, None).is_err());
+    }
+
+    #[test]
+    fn test_project_key_command_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("config.toml");
+        let project = dir.path().join(config::PROJECT_CONFIG);
+        let pwned = dir.path().join("pwned");
+        fs::write(&user, "[openai]\nkey_command = \"echo mine\"\n").unwrap();
+        fs::write(
+            &project,
+            format!(
+                "[deepseek]\nkey_command = \"touch {}; echo theirs\"\napi_key = \"theirs\"\n[openai]\nkey_command = \"echo theirs\"\n",
+                pwned.display()
+            ),
+        )
+        .unwrap();
+
+        let paths = config::ConfigPaths { user: Some(user), project: Some(project) };
+        let mut problems = Vec::new();
+        let config = Config::from_layers(&paths, Vec::new(), &mut problems);
+        let key = |provider| key_from_config(&config, provider, None, None).unwrap();
+        assert_eq!(key("deepseek"), None);
+        assert_eq!(key("openai").as_deref(), Some("mine"));
+        assert!(!pwned.exists());
+        assert!(problems[0].contains("deepseek.key_command"), "{}", problems[0]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secrets_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let secrets = dir.path().join("ai-cli").join("secrets.toml");
+        create_private(&secrets).unwrap();
+        assert_eq!(fs::metadata(&secrets).unwrap().permissions().mode() & 0o777, 0o600);
+
+        fs::write(&secrets, "deepseek = \"sk-1\"\n").unwrap();
+        fs::set_permissions(&secrets, fs::Permissions::from_mode(0o644)).unwrap();
+        let error = lookup("deepseek", None, None, Some(&secrets), None).unwrap_err();
+        assert!(error.to_string().contains("chmod 600"), "{}", error);
+
+        // storing a key restricts the file again, so the key can be read back
+        write_api_key(&secrets, "openai", "sk-2").unwrap();
+        assert_eq!(fs::metadata(&secrets).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(lookup("openai", None, None, Some(&secrets), None).unwrap().as_deref(), Some("sk-2"));
+    }
+}
@@ -1,139 +1,214 @@
 use std::fs;
+use crate::api::config;
+use crate::api::secrets;
+use crate::api::conversation::ReasoningStyle;
 use std::path::Path;
 
+// the settings file of earlier versions, moved into the config by `check_file`
+const SETTING_FILE: &str = "setting.ai";
+
+/// Read the setting `key` (a `setting.ai` style name such as `OPENAI_MODEL`) from the
+/// layered config, if it is set.
+pub fn read_setting(key: &str) -> Option<String> {
+    config::current().get(key)
+}
+
+/// The Deepseek API key, empty if there is none or it could not be read.
 pub fn read_deepseek_api() -> String {
-    let file_path = "setting.ai";
-    match fs::read_to_string(file_path) {
-        Ok(contents) => {
-            for line in contents.lines() {
-                let parts: Vec<&str> = line.split('=').collect();
-                if parts.len() != 2 {
-                    continue;
-                }
-                if parts[0] == "DEEPSEEK_API" {
-                    return parts[1].to_string();
-                }
-            }
-            String::new()
-        },
-        Err(e) => {
-            println!("Failed to read file: {}", e);
-            String::new()
-        }
-    }
+    secrets::api_key("deepseek").unwrap_or_else(|e| {
+        eprintln!("{:#}", e);
+        None
+    })
+    .unwrap_or_default()
 }
 
+/// Store the Deepseek API key in the secrets file.
 pub fn write_deepseek_api(api_key: &str) {
-    let file_path = "setting.ai";
-    let mut new_contents = String::new();
-    let mut found = false;
-
-    match fs::read_to_string(file_path) {
-        Ok(contents) => {
-            for line in contents.lines() {
-                let parts: Vec<&str> = line.split('=').collect();
-                if parts.len() == 2 && parts[0] == "DEEPSEEK_API" {
-                    new_contents.push_str(&format!("DEEPSEEK_API={}\n", api_key));
-                    found = true;
-                } else {
-                    new_contents.push_str(line);
-                    new_contents.push('\n');
-                }
-            }
-            if !found {
-                new_contents.push_str(&format!("DEEPSEEK_API={}\n", api_key));
-            }
-        },
-        Err(_) => {
-            new_contents.push_str(&format!("DEEPSEEK_API={}\n", api_key));
-        }
+    match secrets::store_api_key("deepseek", api_key) {
+        Ok(file) => println!("Deepseek API key stored in {}.", file.display()),
+        Err(e) => eprintln!("Failed to store the API key: {:#}", e),
     }
+}
+
+// Terminals in the same directory each keep their own resume file, stored per
+// session in the user config. The session is `AI_CLI_SESSION` if set, else the
+// terminal: its tty on Linux, `TERM_SESSION_ID` (macOS) or `WT_SESSION` (Windows
+// Terminal), and failing those the shell ai-cli was started from. Only the most
+// recent sessions of a directory are kept; a new terminal that gets the tty or shell
+// process id of an old one picks up its chat. The directory's `resume_file` is the
+// fallback.
+const MAX_SESSIONS: usize = 16;
+
+fn session_id(session: Option<&str>) -> Option<String> {
+    let session: String = session?.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_').collect();
+    Some(session).filter(|session| !session.is_empty())
+}
 
-    match fs::write(file_path, new_contents) {
-        Ok(_) => println!("DEEPSEEK_API updated successfully."),
-        Err(e) => println!("Failed to update DEEPSEEK_API: {}", e),
+fn current_session() -> Option<String> {
+    if let Ok(session) = std::env::var("AI_CLI_SESSION") {
+        return session_id(Some(&session));
+    }
+    if let Some(tty) = terminal_name() {
+        return Some(tty);
+    }
+    for name in ["TERM_SESSION_ID", "WT_SESSION"] {
+        if let Some(session) = session_id(std::env::var(name).ok().as_deref()) {
+            return Some(session);
+        }
     }
+    #[cfg(unix)]
+    return Some(std::os::unix::process::parent_id().to_string());
+    #[cfg(not(unix))]
+    None
+}
+
+// the terminal stdin is connected to, e.g. `tty-pts-3` for `/dev/pts/3`
+fn terminal_name() -> Option<String> {
+    let device = fs::read_link("/proc/self/fd/0").ok()?;
+    let name = device.to_str()?.strip_prefix("/dev/")?;
+    let name = (name.starts_with("pts/") || name.starts_with("tty")).then(|| format!("tty-{}", name))?;
+    session_id(Some(&name.replace('/', "-")))
 }
 
+fn current_directory() -> String {
+    std::env::current_dir().map(|dir| dir.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Make `resume_file` the chat file of this terminal session and of the directory.
 pub fn write_resume_file(resume_file: &str) {
-    let file_path = "setting.ai";
-    let mut new_contents = String::new();
-    let mut found = false;
-
-    match fs::read_to_string(file_path) {
-        Ok(contents) => {
-            for line in contents.lines() {
-                let parts: Vec<&str> = line.split('=').collect();
-                if parts.len() == 2 && parts[0] == "RESUME_FILE" {
-                    new_contents.push_str(&format!("RESUME_FILE={}\n", resume_file));
-                    found = true;
-                } else {
-                    new_contents.push_str(line);
-                    new_contents.push('\n');
-                }
-            }
-            if !found {
-                new_contents.push_str(&format!("RESUME_FILE={}\n", resume_file));
-            }
-        },
-        Err(_) => {
-            new_contents.push_str(&format!("RESUME_FILE={}\n", resume_file));
-        }
+    let Some(file) = config::user_config_path() else {
+        eprintln!("Failed to remember {}: no config directory, set XDG_CONFIG_HOME", resume_file);
+        return;
+    };
+    if let Err(e) = store_resume_file(&file, &current_directory(), current_session().as_deref(), resume_file) {
+        eprintln!("Failed to remember {}: {}", resume_file, e);
     }
+}
 
-    match fs::write(file_path, new_contents) {
-        Ok(_) => println!("RESUME_FILE updated successfully."),
-        Err(e) => println!("Failed to update RESUME_FILE: {}", e),
+fn store_resume_file(user: &Path, directory: &str, session: Option<&str>, resume_file: &str) -> Result<(), String> {
+    let value = toml::Value::String(resume_file.to_string());
+    config::write_value(user, &["directories", directory, "resume_file"], value, true)?;
+    match session {
+        Some(session) => config::write_session(user, directory, session, resume_file, MAX_SESSIONS),
+        None => Ok(()),
     }
 }
 
+/// The chat file of this terminal session, falling back to the last one chosen in the
+/// directory. Empty if none was chosen yet.
 pub fn read_resume_file() -> String {
-    let file_path = "setting.ai";
-    match fs::read_to_string(file_path) {
-        Ok(contents) => {
-            for line in contents.lines() {
-                let parts: Vec<&str> = line.split('=').collect();
-                if parts.len() != 2 {
-                    continue;
-                }
-                if parts[0] == "RESUME_FILE" {
-                    return parts[1].to_string();
-                }
-            }
-            String::new()
-        },
-        Err(e) => {
-            println!("Failed to read file: {}", e);
-            String::new()
-        }
-    }
+    resume_file_in(&config::current(), &current_directory(), current_session().as_deref())
+}
+
+fn resume_file_in(config: &config::Config, directory: &str, session: Option<&str>) -> String {
+    let Some(state) = config.directories.get(directory) else {
+        return String::new();
+    };
+    session
+        .and_then(|session| state.sessions.get(session).cloned())
+        .or_else(|| state.resume_file.clone())
+        .unwrap_or_default()
+}
+
+/// Name of the chat backend selected with `PROVIDER=...`, defaulting to Deepseek.
+pub fn read_provider() -> String {
+    read_setting("PROVIDER").unwrap_or_else(|| "deepseek".to_string())
+}
+
+/// Whether replies should be streamed as they are generated (`STREAM=false` turns it off).
+pub fn read_stream() -> bool {
+    read_setting("STREAM").map(|value| value.trim() != "false").unwrap_or(true)
+}
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are a helper assistant";
+
+/// System prompt of conversations started outside a chat file, `SYSTEM_PROMPT=...`.
+pub fn read_system_prompt() -> String {
+    read_setting("SYSTEM_PROMPT")
+        .filter(|prompt| !prompt.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_SYSTEM_PROMPT.to_string())
 }
 
+/// Layout of reasoning traces in the chat file, `REASONING_STYLE=details` (default) or `section`.
+pub fn read_reasoning_style() -> ReasoningStyle {
+    read_setting("REASONING_STYLE")
+        .and_then(|name| ReasoningStyle::from_name(&name))
+        .unwrap_or_default()
+}
+
+/// Move a `setting.ai` file of an earlier version in the working directory into the
+/// config, keeping it as `setting.ai.bak`.
 pub fn check_file() {
-    let file_path = "setting.ai";
-    let file_content = "DEEPSEEK_API=To_BE_FILLED_BY_PROGRAM\nRESUME_FILE=To_BE_FILLED_BY_PROGRAM\n";
-    if !Path::new(file_path).exists() {
-        match fs::write(file_path, file_content) {
-            Ok(_) => println!("File 'setting.ai' created successfully."),
-            Err(e) => println!("Failed to create file: {}", e),
+    let Ok(contents) = fs::read_to_string(SETTING_FILE) else {
+        return;
+    };
+    let (Some(user), Some(secrets)) = (config::user_config_path(), secrets::secrets_path()) else {
+        eprintln!("Cannot move {} into the config: no config directory, set XDG_CONFIG_HOME", SETTING_FILE);
+        return;
+    };
+    let directory = current_directory();
+    let targets = config::MigrationTargets {
+        user: &user,
+        directory: &directory,
+        secrets: &secrets,
+        project: Path::new(config::PROJECT_CONFIG),
+    };
+    match config::migrate_setting_file(&contents, &targets) {
+        Ok((moved, skipped)) => {
+            for line in skipped {
+                eprintln!("Not moved from {}: {}", SETTING_FILE, line);
+            }
+            // the keys are in the secrets file now, the backup keeps everything else
+            let backup = format!("{}.bak", SETTING_FILE);
+            if let Err(e) = fs::write(&backup, config::without_api_keys(&contents)).and_then(|_| fs::remove_file(SETTING_FILE)) {
+                eprintln!("Failed to replace {} with {}: {}", SETTING_FILE, backup, e);
+            }
+            eprintln!(
+                "Moved {} settings from {} to {} (chat files, endpoints), {} (API keys) and {}, the old file is kept without its API keys as {}.",
+                moved.len(),
+                SETTING_FILE,
+                user.display(),
+                secrets.display(),
+                config::PROJECT_CONFIG,
+                backup
+            );
         }
-    } 
+        Err(e) => eprintln!("Failed to move {} into the config: {}", SETTING_FILE, e),
+    }
 }
+
 mod test {
     #[allow(unused_imports)]
     use super::*;
 
     #[test]
-    fn test_check_file() {
-        check_file();
-        write_deepseek_api("test_key");
-        let api_key = read_deepseek_api();
-        assert_eq!(api_key, "test_key");
-        write_deepseek_api("test_key_1");
-        let api_key = read_deepseek_api();
-        assert_eq!(api_key, "test_key_1");
-        write_resume_file("sample.md_2");
-        let resume_file = read_resume_file();
-        assert_eq!(resume_file, "sample.md_2");
+    fn test_session_id() {
+        assert_eq!(session_id(Some("4711")).as_deref(), Some("4711"));
+        assert_eq!(session_id(Some("tab 2=x\n")).as_deref(), Some("tab2x"));
+        assert_eq!(session_id(Some("==")), None);
+        assert_eq!(session_id(None), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_key_and_resume_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("ai-cli").join("config.toml");
+        let secrets_file = dir.path().join("ai-cli").join("secrets.toml");
+        let read_key = || secrets::lookup("deepseek", None, None, Some(&secrets_file), None).unwrap();
+
+        secrets::write_api_key(&secrets_file, "deepseek", "test_key").unwrap();
+        assert_eq!(read_key().as_deref(), Some("test_key"));
+        secrets::write_api_key(&secrets_file, "deepseek", "test_key_1").unwrap();
+        assert_eq!(read_key().as_deref(), Some("test_key_1"));
+
+        store_resume_file(&user, "/work", Some("tty-pts-1"), "sample.md_2").unwrap();
+        store_resume_file(&user, "/work", Some("tty-pts-2"), "other.md").unwrap();
+        let paths = config::ConfigPaths { user: Some(user), project: None };
+        let config = config::Config::from_layers(&paths, Vec::new(), &mut Vec::new());
+        assert_eq!(resume_file_in(&config, "/work", Some("tty-pts-1")), "sample.md_2");
+        // a new terminal starts with the file chosen last
+        assert_eq!(resume_file_in(&config, "/work", Some("tty-pts-3")), "other.md");
+        assert_eq!(resume_file_in(&config, "/work", None), "other.md");
+        assert_eq!(resume_file_in(&config, "/elsewhere", Some("tty-pts-1")), "");
+    }
+}
@@ -0,0 +1,63 @@
+// Incremental decoder for `text/event-stream` bodies. Bytes are pushed in as they
+// arrive from the network and the `data:` payload of every completed event is returned.
+#[derive(Debug, Default)]
+pub(crate) struct SseDecoder {
+    buffer: Vec<u8>,
+    data_lines: Vec<String>,
+}
+
+impl SseDecoder {
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                // a blank line dispatches the event collected so far
+                if !self.data_lines.is_empty() {
+                    events.push(self.data_lines.join("\n"));
+                    self.data_lines.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data_lines.push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+            // comments (`: keep-alive`) and `event:`/`id:`/`retry:` fields are ignored
+        }
+        events
+    }
+
+    // flush an event that was not terminated by a blank line before the stream closed
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        let mut events = self.push(b"\n\n");
+        events.pop()
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_events_split_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b"data: {\"a\"").is_empty());
+        assert_eq!(decoder.push(b":1}\n\ndata: {\"b\":2}\r\n\r\n"), vec!["{\"a\":1}", "{\"b\":2}"]);
+        assert!(decoder.finish().is_none());
+    }
+
+    #[test]
+    fn test_comments_and_multiline_data() {
+        let mut decoder = SseDecoder::default();
+        let events = decoder.push(b": keep-alive\n\nevent: message\ndata: line one\ndata: line two\n\n");
+        assert_eq!(events, vec!["line one\nline two"]);
+    }
+
+    #[test]
+    fn test_unterminated_event_is_flushed() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b"data: [DONE]").is_empty());
+        assert_eq!(decoder.finish().unwrap(), "[DONE]");
+    }
+}
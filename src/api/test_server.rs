@@ -0,0 +1,116 @@
+// A tiny HTTP/1.1 stand-in server used by the provider tests. It answers each
+// incoming connection with the next canned response and hands the raw requests back.
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+pub struct CannedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl CannedResponse {
+    pub fn json(status: u16, body: &str) -> CannedResponse {
+        CannedResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> CannedResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_content_type(status: u16, content_type: &str, body: &str) -> CannedResponse {
+        CannedResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.to_string(),
+        }
+    }
+}
+
+pub struct RecordedRequest {
+    pub head: String,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if key.trim().eq_ignore_ascii_case(name) {
+                Some(value.trim().to_string())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn path(&self) -> String {
+        self.head.split_whitespace().nth(1).unwrap_or_default().to_string()
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+// Serve `responses` in order, one per connection, and return the base url
+// (`http://127.0.0.1:<port>`) together with a handle yielding the recorded requests.
+pub async fn serve(responses: Vec<CannedResponse>) -> (String, JoinHandle<Vec<RecordedRequest>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = tokio::spawn(async move {
+        let mut recorded = Vec::new();
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            recorded.push(read_request(&mut socket).await);
+            let mut raw = format!("HTTP/1.1 {} Canned\r\n", response.status);
+            for (name, value) in &response.headers {
+                raw.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            raw.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+            raw.push_str(&response.body);
+            socket.write_all(raw.as_bytes()).await.unwrap();
+            socket.shutdown().await.ok();
+        }
+        recorded
+    });
+    (base_url, handle)
+}
+
+async fn read_request(socket: &mut tokio::net::TcpStream) -> RecordedRequest {
+    let mut data = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = socket.read(&mut chunk).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&chunk[..n]);
+        let text = String::from_utf8_lossy(&data).to_string();
+        if let Some(split) = text.find("\r\n\r\n") {
+            let head = text[..split].to_string();
+            let content_length = head
+                .lines()
+                .find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    if key.trim().eq_ignore_ascii_case("content-length") {
+                        value.trim().parse::<usize>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(0);
+            if data.len() >= split + 4 + content_length {
+                let body = String::from_utf8_lossy(&data[split + 4..split + 4 + content_length]).to_string();
+                return RecordedRequest { head, body };
+            }
+        }
+    }
+    RecordedRequest { head: String::from_utf8_lossy(&data).to_string(), body: String::new() }
+}
@@ -0,0 +1,115 @@
+use crate::{chat_provider, drop_empty_question, print_dry_run, quiet, report_chat_error};
+use ai_cli::api::conversation::{Conversation, Message, RoleOrder};
+use ai_cli::api::md_paraser::parse_markdown_file;
+use ai_cli::api::meta::ConversationMeta;
+use ai_cli::api::params::ChatParams;
+use ai_cli::api::provider::StreamEvent;
+use ai_cli::api::setter::{read_reasoning_style, read_system_prompt};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process;
+
+// The prompt followed by whatever was piped in, e.g. `git diff | ai-cli ask "review this"`.
+fn build_question(prompt: Option<&str>, piped: Option<&str>) -> Option<String> {
+    let prompt = prompt.map(str::trim).filter(|prompt| !prompt.is_empty());
+    let piped = piped.map(|input| input.trim_end()).filter(|input| !input.trim().is_empty());
+    match (prompt, piped) {
+        (Some(prompt), Some(input)) => Some(format!("{}\n\n{}", prompt, input)),
+        (Some(text), None) | (None, Some(text)) => Some(text.to_string()),
+        (None, None) => None,
+    }
+}
+
+/// Answer one question without a chat file. Only the answer goes to stdout, so it can
+/// be piped on; the reasoning trace and errors go to stderr.
+pub async fn run(prompt: Option<String>, file: Option<String>, dry_run: bool, flags: ChatParams) {
+    let mut piped = None;
+    if !io::stdin().is_terminal() {
+        let mut input = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut input) {
+            eprintln!("Failed to read stdin: {}", e);
+            process::exit(1);
+        }
+        piped = Some(input);
+    }
+    let question = build_question(prompt.as_deref(), piped.as_deref()).unwrap_or_else(|| {
+        eprintln!("Nothing to ask: give a prompt or pipe the input in");
+        process::exit(2);
+    });
+
+    let mut conversation = Conversation::new(read_system_prompt());
+    conversation.user(question.clone()).expect("a User message always follows the System prompt");
+    let ai = chat_provider(&conversation, flags).unwrap_or_else(|e| {
+        eprintln!("{:#}", e);
+        process::exit(1);
+    });
+    if dry_run {
+        print_dry_run(ai.as_ref(), &conversation);
+        return;
+    }
+
+    let mut in_reasoning = false;
+    let mut on_event = |event: StreamEvent| match event {
+        StreamEvent::Reasoning(_) if quiet() => {}
+        StreamEvent::Reasoning(delta) => {
+            in_reasoning = true;
+            eprint!("{}", delta);
+        }
+        StreamEvent::Content(delta) => {
+            if in_reasoning {
+                eprintln!("\n");
+                in_reasoning = false;
+            }
+            print!("{}", delta);
+            let _ = io::stdout().flush();
+        }
+    };
+    let response = match ai.chat_stream(conversation, &mut on_event).await {
+        Ok(response) => response,
+        Err(e) => {
+            report_chat_error(ai.as_ref(), &e);
+            process::exit(1);
+        }
+    };
+    println!();
+
+    if let Some(file) = file {
+        if let Err(e) = append_exchange(&file, question, response.message) {
+            eprintln!("Failed to write {}: {}", file, e);
+            process::exit(1);
+        }
+        if !quiet() {
+            eprintln!("Appended to {}.", file);
+        }
+    }
+}
+
+// Add the question and answer at the end of `file`, starting a new chat file if needed.
+fn append_exchange(file: &str, question: String, answer: Message) -> Result<(), String> {
+    let mut conversation = if Path::new(file).exists() {
+        parse_markdown_file(file).map_err(|e| e.to_string())?
+    } else {
+        let mut conversation = Conversation::new(read_system_prompt());
+        conversation.set_meta(Some(ConversationMeta::new_chat(file)));
+        conversation
+    };
+    conversation.set_role_order(RoleOrder::Relaxed);
+    conversation.set_reasoning_style(read_reasoning_style());
+    drop_empty_question(&mut conversation);
+    conversation.user(question).map_err(|e| e.to_string())?;
+    conversation.add_message(answer).map_err(|e| e.to_string())?;
+    std::fs::write(file, conversation.to_markdown(file.to_string())).map_err(|e| e.to_string())
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_build_question() {
+        assert_eq!(build_question(Some("review this"), Some("diff\n\n")), Some("review this\n\ndiff".to_string()));
+        assert_eq!(build_question(None, Some("just the input\n")), Some("just the input".to_string()));
+        assert_eq!(build_question(Some(" hi "), Some("  \n")), Some("hi".to_string()));
+        assert_eq!(build_question(Some(""), None), None);
+    }
+}
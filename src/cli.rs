@@ -0,0 +1,168 @@
+use ai_cli::api::params::ChatParams;
+use clap::{Args, Parser, Subcommand};
+
+/// A CLI tool to chat with Deepseek AI and save the conversation to a Markdown file
+#[derive(Debug, Parser)]
+#[command(name = "ai-cli", version, after_help = EXAMPLES)]
+pub struct Cli {
+    /// Only print replies and errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+const EXAMPLES: &str = r#"Examples:
+  ai-cli set chat.md
+  ai-cli key <api_key>
+  ai-cli chat
+  ai-cli chat notes.md
+  ai-cli chat --model deepseek-chat --temperature 0.3
+  ai-cli repl
+  git diff | ai-cli ask "review this""#;
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Store the Deepseek API key in the private secrets file
+    Key { api_key: String },
+    /// Create a new Markdown file to chat in or use an existing one
+    Set { filename: String },
+    /// Get a response and save the conversation to the Markdown file
+    Chat {
+        #[command(flatten)]
+        file: FileArg,
+        /// Print the request that would be sent instead of sending it
+        #[arg(long)]
+        dry_run: bool,
+        #[command(flatten)]
+        params: ParamArgs,
+    },
+    /// Keep chatting in the terminal, saving every exchange to the Markdown file
+    Repl {
+        #[command(flatten)]
+        file: FileArg,
+        #[command(flatten)]
+        params: ParamArgs,
+    },
+    /// Answer one question, with any input piped on stdin appended to it
+    Ask {
+        prompt: Option<String>,
+        /// Also append the question and answer to this chat file
+        #[arg(long)]
+        file: Option<String>,
+        /// Print the request that would be sent instead of sending it
+        #[arg(long)]
+        dry_run: bool,
+        #[command(flatten)]
+        params: ParamArgs,
+    },
+}
+
+// the chat file of `chat` and `repl`, as an argument or with `--file` like `ask`
+#[derive(Debug, Default, Args)]
+pub struct FileArg {
+    /// Chat file to use, by default the one chosen with `set` in this terminal
+    file: Option<String>,
+    /// Same as the FILE argument
+    #[arg(long = "file", value_name = "FILE", conflicts_with = "file")]
+    file_flag: Option<String>,
+}
+
+impl FileArg {
+    pub fn path(self) -> Option<String> {
+        self.file.or(self.file_flag)
+    }
+}
+
+// the request parameters that can be given on the command line
+#[derive(Debug, Default, Args)]
+pub struct ParamArgs {
+    /// Model to use instead of the configured one
+    #[arg(long)]
+    model: Option<String>,
+    /// Sampling temperature
+    #[arg(long)]
+    temperature: Option<f64>,
+    /// Nucleus sampling probability
+    #[arg(long)]
+    top_p: Option<f64>,
+    /// Maximum number of tokens in the reply
+    #[arg(long)]
+    max_tokens: Option<u32>,
+    /// Penalty for repeating frequent tokens
+    #[arg(long, allow_negative_numbers = true)]
+    frequency_penalty: Option<f64>,
+    /// Penalty for repeating any earlier token
+    #[arg(long, allow_negative_numbers = true)]
+    presence_penalty: Option<f64>,
+    /// Stop sequence, may be given several times
+    #[arg(long)]
+    stop: Vec<String>,
+}
+
+impl From<ParamArgs> for ChatParams {
+    fn from(args: ParamArgs) -> ChatParams {
+        ChatParams {
+            model: args.model,
+            temperature: args.temperature,
+            top_p: args.top_p,
+            max_tokens: args.max_tokens,
+            frequency_penalty: args.frequency_penalty,
+            presence_penalty: args.presence_penalty,
+            stop: args.stop,
+        }
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_chat_flags() {
+        let cli = Cli::try_parse_from([
+            "ai-cli", "chat", "--model", "deepseek-chat", "--temperature=0.2", "--stop", "a", "--stop", "b",
+            "--presence-penalty", "-0.5", "--quiet",
+        ])
+        .unwrap();
+        assert!(cli.quiet);
+        let Command::Chat { file, dry_run, params } = cli.command else { panic!("expected chat") };
+        assert_eq!((file.path(), dry_run), (None, false));
+        let params = ChatParams::from(params);
+        assert_eq!(params.model.as_deref(), Some("deepseek-chat"));
+        assert_eq!(params.temperature, Some(0.2));
+        assert_eq!(params.presence_penalty, Some(-0.5));
+        assert_eq!(params.stop, vec!["a".to_string(), "b".to_string()]);
+
+        assert!(Cli::try_parse_from(["ai-cli", "chat", "--max-tokens", "many"]).is_err());
+        assert!(Cli::try_parse_from(["ai-cli", "chat", "--top-p"]).is_err());
+        assert!(Cli::try_parse_from(["ai-cli", "chat", "--verbose"]).is_err());
+        assert!(Cli::try_parse_from(["ai-cli", "key"]).is_err());
+
+        let cli = Cli::try_parse_from(["ai-cli", "chat", "notes.md", "--dry-run"]).unwrap();
+        let Command::Chat { file, dry_run, .. } = cli.command else { panic!("expected chat") };
+        assert_eq!((file.path().as_deref(), dry_run), (Some("notes.md"), true));
+
+        let cli = Cli::try_parse_from(["ai-cli", "repl", "--file", "notes.md"]).unwrap();
+        let Command::Repl { file, .. } = cli.command else { panic!("expected repl") };
+        assert_eq!(file.path().as_deref(), Some("notes.md"));
+        assert!(Cli::try_parse_from(["ai-cli", "chat", "a.md", "--file", "b.md"]).is_err());
+    }
+
+    #[test]
+    fn test_ask_flags() {
+        let cli = Cli::try_parse_from(["ai-cli", "-q", "ask", "review this", "--file", "review.md", "--dry-run"]).unwrap();
+        let Command::Ask { prompt, file, dry_run, .. } = cli.command else { panic!("expected ask") };
+        assert_eq!(prompt.as_deref(), Some("review this"));
+        assert_eq!(file.as_deref(), Some("review.md"));
+        assert!(dry_run && cli.quiet);
+        assert!(Cli::try_parse_from(["ai-cli", "ask", "review", "this"]).is_err());
+    }
+}
@@ -1,25 +1,20 @@
-use ai_cli::api::ai::DeepseekAi;
-use ai_cli::api::setter::{ write_deepseek_api, check_file , read_resume_file, write_resume_file, read_deepseek_api};
+use ai_cli::api::conversation::{Conversation, Message};
+use ai_cli::api::error::ApiError;
+use ai_cli::api::params::ChatParams;
+use ai_cli::api::meta::ConversationMeta;
+use ai_cli::api::provider::{provider_by_name, provider_from_settings, provider_key, ChatProvider, StreamEvent};
+use ai_cli::api::setter::{ write_deepseek_api, check_file , read_provider, read_resume_file, write_resume_file, read_reasoning_style};
 use ai_cli::api::md_paraser::parse_markdown_file;
-use std::env;
+use ai_cli::api::md_writer::MarkdownStream;
+use clap::Parser;
+use cli::{Cli, Command};
 use std::io::Write;
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-const HELP_TEXT: &str = r#"
-ai-cli - A CLI tool help you chat with Deepseek AI and save the conversation to a Markdown file
-
-COMMANDS:
-    key <api_key>           Set the Deepseek API key
-    set <filename>          Create a new Markdown filet to chat in or use an existing one
-    chat                    get a response from Deepseek AI and save the conversation to the Markdown file
-    help                    Show this help message
-
-EXAMPLES:
-    ai-cli set chat.md
-    ai-cli key <api_key>
-    ai-cli chat
-
-"#;
+mod ask;
+mod cli;
+mod repl;
 
 const SAMPLE_MARKDOWN: &str = r#"
 
@@ -35,59 +30,216 @@ You are a helper assistant
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    QUIET.store(cli.quiet, Ordering::Relaxed);
     check_file();
-    let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        print_help_and_exit();
+    match cli.command {
+        Command::Set { filename } => create_markdown_file(&filename),
+        Command::Key { api_key } => write_deepseek_api(&api_key),
+        Command::Chat { file, dry_run, params } => {
+            let (filename, mut conversation, ai) = load_chat(file.path(), params.into());
+            if !matches!(conversation.messages().last(), Some(Message::User(_))) {
+                eprintln!("Nothing to answer in {}: add a `### User` section at the end first.", filename);
+                process::exit(1);
+            }
+            if dry_run {
+                print_dry_run(ai.as_ref(), &conversation);
+                return;
+            }
+            if !quiet() {
+                print!("Starting chat session with {}...\n\n", ai.name());
+                print!("{}", conversation.to_markdown(filename.clone()));
+            }
+            match chat_once(ai.as_ref(), &mut conversation, &filename).await {
+                ChatOutcome::Answered => {}
+                ChatOutcome::Failed => process::exit(1),
+                ChatOutcome::Interrupted => process::exit(130),
+            }
+        }
+        Command::Ask { prompt, file, dry_run, params } => ask::run(prompt, file, dry_run, params.into()).await,
+        Command::Repl { file, params } => {
+            let (filename, mut conversation, ai) = load_chat(file.path(), params.into());
+            repl::run(ai.as_ref(), &mut conversation, &filename).await;
+        }
     }
+}
 
-    match args[1].as_str() {
-        "set" => {
-            if args.len() < 3 {
-                eprintln!("Missing <chat_file> argument");
-                print_help_and_exit();
-            }
-            create_markdown_file(&args[2]);
+// set by `--quiet`, which leaves only replies and errors on the terminal
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+// `--dry-run`: the body of the request as it would be sent, without sending it
+fn print_dry_run(ai: &dyn ChatProvider, conversation: &Conversation) {
+    eprintln!("Would send this request to {}:", ai.name());
+    println!("{}", serde_json::to_string_pretty(&ai.request_body(conversation)).unwrap_or_default());
+}
+
+// The chat file, `file` or else the resume file of this terminal, with the provider to continue it
+// with, exiting on errors.
+fn load_chat(file: Option<String>, flags: ChatParams) -> (String, Conversation, Box<dyn ChatProvider>) {
+    let filename = file.unwrap_or_else(read_resume_file);
+    if filename.is_empty() {
+        eprintln!("No chat file given: pass one, e.g. `ai-cli chat chat.md`, or pick one with `ai-cli set chat.md`.");
+        process::exit(1);
+    }
+    let mut conversation = match parse_markdown_file(&filename) {
+        Ok(conversation) => conversation,
+        Err(e) => {
+            eprintln!("{}", e.diagnostic());
+            process::exit(1);
         }
-        "key" => {
-            if args.len() < 3 {
-                eprintln!("Missing <api_key> argument");
-                print_help_and_exit();
-            }
-            write_deepseek_api(&args[2]);
+    };
+    conversation.set_reasoning_style(read_reasoning_style());
+    let ai = match chat_provider(&conversation, flags) {
+        Ok(ai) => ai,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            process::exit(1);
         }
-        "chat" => {
-            let api_key = read_deepseek_api();
-            let ai = DeepseekAi::new(api_key);
-            let filename = read_resume_file();
-            let mut conversation = parse_markdown_file(&filename).unwrap();
-            print!("Starting chat session with Deepseek AI...\n\n");
-            print!("{}", conversation.to_markdown(filename.clone()));
-            match ai.chat(conversation.clone()).await {
-                Ok(ai_message) => {
-                    conversation.add_message(ai_message.clone());
-                    let markdown = conversation.to_markdown(filename.clone());
-                    overwrite_markdown_file(&filename, markdown);
-                }
-                Err(e) => {
-                    eprintln!("Error during chat session: {}", e);
+    };
+    (filename, conversation, ai)
+}
+
+// Provider and parameters come from the settings, overridden by the chat file's
+// front matter, overridden by the flags.
+fn chat_provider(conversation: &Conversation, flags: ChatParams) -> anyhow::Result<Box<dyn ChatProvider>> {
+    let meta = conversation.meta().cloned().unwrap_or_default();
+    let mut settings = ChatParams::from_settings()?;
+    // `[params] model` is a model of the configured provider; a chat file switching to
+    // another one gets that provider's own model
+    if meta.provider.as_deref().is_some_and(|provider| provider_key(provider) != provider_key(&read_provider())) {
+        settings.model = None;
+    }
+    let params = settings.overlay(meta.chat_params()).overlay(flags);
+    match meta.provider {
+        Some(provider) => provider_by_name(&provider, params),
+        None => provider_from_settings(params),
+    }
+}
+
+const INTERRUPTED_MARKER: &str = "*[interrupted]*";
+
+#[derive(Debug, PartialEq, Eq)]
+enum ChatOutcome {
+    Answered,
+    Failed,
+    Interrupted,
+}
+
+// Send the conversation, stream the reply to the terminal and into `filename`, and
+// store the final message. Ctrl-C aborts the request but keeps what arrived so far.
+async fn chat_once(ai: &dyn ChatProvider, conversation: &mut Conversation, filename: &str) -> ChatOutcome {
+    let mut printer = StreamPrinter::default();
+    let mut partial = String::new();
+    let mut partial_reasoning = String::new();
+    let mut file_stream = match MarkdownStream::begin(filename, conversation, filename.to_string()) {
+        Ok(stream) => Some(stream),
+        Err(e) => {
+            eprintln!("Failed to stream into {}: {}", filename, e);
+            None
+        }
+    };
+    let result = {
+        let mut on_event = |event: StreamEvent| {
+            if let Some(stream) = file_stream.as_mut() {
+                if let Err(e) = stream.push(&event) {
+                    eprintln!("Failed to stream into {}: {}", filename, e);
+                    file_stream = None;
                 }
             }
+            match &event {
+                StreamEvent::Reasoning(delta) => partial_reasoning.push_str(delta),
+                StreamEvent::Content(delta) => partial.push_str(delta),
+            }
+            printer.print(event);
+        };
+        let request = ai.chat_stream(conversation.clone(), &mut on_event);
+        tokio::select! {
+            result = request => Some(result),
+            _ = tokio::signal::ctrl_c() => None,
         }
-        "help" | "-h" | "--help" => {
-            print_help_and_exit();
+    };
+    println!();
+    let outcome = match result {
+        Some(Ok(response)) => match conversation.add_message(response.message) {
+            Ok(()) => ChatOutcome::Answered,
+            Err(e) => {
+                eprintln!("Cannot store the reply: {}", e);
+                ChatOutcome::Failed
+            }
+        },
+        Some(Err(e)) => {
+            report_chat_error(ai, &e);
+            ChatOutcome::Failed
         }
-        _ => {
-            eprintln!("Unknown command: {}", args[1]);
-            print_help_and_exit();
+        None => {
+            eprintln!("Interrupted, keeping the partial reply.");
+            let message = Message::Assistant {
+                content: format!("{}\n\n{}", partial, INTERRUPTED_MARKER),
+                reasoning: Some(partial_reasoning).filter(|r| !r.is_empty()),
+                usage: None,
+            };
+            if let Err(e) = conversation.add_message(message) {
+                eprintln!("Cannot store the partial reply: {}", e);
+            }
+            ChatOutcome::Interrupted
         }
+    };
+    // rewrite the file from the conversation; after a failure this also drops
+    // the unfinished Assistant section again
+    let markdown = conversation.to_markdown(filename.to_string());
+    overwrite_markdown_file(filename, markdown);
+    outcome
+}
+
+fn report_chat_error(ai: &dyn ChatProvider, e: &anyhow::Error) {
+    match e.downcast_ref::<ApiError>() {
+        Some(api_error) => eprintln!("{} error: {}\n{}", ai.name(), api_error, api_error.hint()),
+        None => eprintln!("Error during chat session: {:#}", e),
     }
 }
 
-fn print_help_and_exit() {
-    println!("{}", HELP_TEXT);
-    process::exit(0);
+// the empty `### User` section new chat files end with is filled in by `repl` and `ask`
+fn drop_empty_question(conversation: &mut Conversation) {
+    if matches!(conversation.messages().last(), Some(Message::User(text)) if text.trim().is_empty()) {
+        conversation.pop_message();
+    }
+}
+
+// Echoes streamed deltas to the terminal, with a heading for the reasoning trace
+#[derive(Default)]
+struct StreamPrinter {
+    in_reasoning: bool,
+    in_content: bool,
+}
+
+impl StreamPrinter {
+    fn print(&mut self, event: StreamEvent) {
+        match event {
+            StreamEvent::Reasoning(_) if quiet() => return,
+            StreamEvent::Reasoning(delta) => {
+                if !self.in_reasoning {
+                    println!("Reasoning:");
+                    self.in_reasoning = true;
+                }
+                print!("{}", delta);
+            }
+            StreamEvent::Content(delta) => {
+                if !self.in_content {
+                    if self.in_reasoning {
+                        print!("\n\nAnswer:\n");
+                    }
+                    self.in_content = true;
+                }
+                print!("{}", delta);
+            }
+        }
+        let _ = std::io::stdout().flush();
+    }
 }
 
 fn create_markdown_file(filename: &str) {
@@ -105,7 +257,8 @@ fn create_markdown_file(filename: &str) {
         .open(&filename)
     {
         Ok(mut file) => {
-            let mut content = format!("# {}\n\n", filename);
+            let mut content = ConversationMeta::new_chat(&filename).to_markdown();
+            content.push_str(&format!("# {}\n\n", filename));
             content.push_str(SAMPLE_MARKDOWN);
             if let Err(e) = file.write_all(content.as_bytes()) {
                 eprintln!("Failed to write to file {}: {}", filename, e);
@@ -123,24 +276,20 @@ fn create_markdown_file(filename: &str) {
     write_resume_file(&filename);
 }
 
+// Replace the contents of the chat file at exactly `filename`, the path it was read
+// from; a file that has gone missing is reported, not created again.
 fn overwrite_markdown_file(filename: &str, content: String) {
-    let mut filename = filename.to_string();
-
-    if !filename.ends_with(".md") {
-        filename.push_str(".md");
-    }
-
     use std::fs::OpenOptions;
     // overwrite the file with content anyway
     match OpenOptions::new()
         .write(true)
         .truncate(true) // truncate will ensure the file is overwritten
-        .open(&filename)
+        .open(filename)
     {
         Ok(mut file) => {
             if let Err(e) = file.write_all(content.as_bytes()) {
                 eprintln!("Failed to write to file {}: {}", filename, e);
-            } else {
+            } else if !quiet() {
                 println!("File {} overwritten successfully.", filename);
             }
         }
@@ -0,0 +1,241 @@
+use crate::{chat_once, drop_empty_question, overwrite_markdown_file, ChatOutcome};
+use ai_cli::api::conversation::{Conversation, Message};
+use ai_cli::api::provider::ChatProvider;
+use ai_cli::api::setter::write_resume_file;
+use std::io::{self, BufRead, Write};
+use tokio::sync::mpsc;
+
+const REPL_HELP: &str = r#"
+Type a message and press Enter to send it. End a line with `\` to continue on the
+next one, or put the message between two `"""` lines. An empty message sends the
+question the chat file ends with, if it has not been answered yet.
+
+    /system <text>   Add a System message
+    /retry           Drop the last reply and ask again
+    /save [file]     Write the chat to the file now, or to another file from now on
+    /help            Show this help
+    /quit            Leave (Ctrl-D works too)
+
+Ctrl-C stops a reply while it is written and keeps what arrived; at the prompt it
+leaves like /quit.
+"#;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    System(String),
+    Retry,
+    Save(Option<String>),
+    Help,
+    Quit,
+    Unknown(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+    Prompt(String),
+    Command(Command),
+    Eof,
+}
+
+fn parse_command(line: &str) -> Command {
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    match name {
+        "/system" => Command::System(rest.to_string()),
+        "/retry" => Command::Retry,
+        "/save" => Command::Save(Some(rest.to_string()).filter(|file| !file.is_empty())),
+        "/help" => Command::Help,
+        "/quit" | "/exit" => Command::Quit,
+        _ => Command::Unknown(name.to_string()),
+    }
+}
+
+// Read one message or command. `out` gets the continuation prompt of multiline input.
+fn read_input(reader: &mut impl BufRead, out: &mut impl Write) -> io::Result<Input> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_block = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(if lines.is_empty() && !in_block { Input::Eof } else { Input::Prompt(lines.join("\n")) });
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        if in_block {
+            if line.trim() == "\"\"\"" {
+                return Ok(Input::Prompt(lines.join("\n")));
+            }
+            lines.push(line.to_string());
+        } else if lines.is_empty() && line.trim_start().starts_with('/') {
+            return Ok(Input::Command(parse_command(line.trim())));
+        } else if lines.is_empty() && line.trim() == "\"\"\"" {
+            in_block = true;
+        } else if let Some(line) = line.strip_suffix('\\') {
+            lines.push(line.to_string());
+        } else {
+            lines.push(line.to_string());
+            return Ok(Input::Prompt(lines.join("\n")));
+        }
+        write!(out, "... ")?;
+        out.flush()?;
+    }
+}
+
+// Read the input on a thread of its own, so waiting for the next line neither blocks
+// the runtime nor keeps us from noticing Ctrl-C. The thread is left blocked on stdin
+// when the REPL ends; it goes away with the process.
+fn spawn_reader() -> mpsc::UnboundedReceiver<io::Result<Input>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        loop {
+            let input = read_input(&mut stdin, &mut io::stdout());
+            let last = !matches!(input, Ok(Input::Prompt(_) | Input::Command(_)));
+            if sender.send(input).is_err() || last {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+/// Chat in the terminal, writing `filename` after every exchange.
+pub async fn run(ai: &dyn ChatProvider, conversation: &mut Conversation, filename: &str) {
+    let mut filename = filename.to_string();
+    drop_empty_question(conversation);
+    println!("Chatting with {} in {}, /help lists the commands.", ai.name(), filename);
+
+    let mut inputs = spawn_reader();
+    loop {
+        print!("\n> ");
+        let _ = io::stdout().flush();
+        // `chat_once` listens for Ctrl-C too, which takes over the default of ending
+        // the process, so it is handled here as well
+        let input = tokio::select! {
+            input = inputs.recv() => input.unwrap_or(Ok(Input::Eof)),
+            _ = tokio::signal::ctrl_c() => {
+                println!();
+                return;
+            }
+        };
+        let input = match input {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Failed to read the input: {}", e);
+                return;
+            }
+        };
+        match input {
+            Input::Eof | Input::Command(Command::Quit) => return,
+            Input::Command(Command::Help) => println!("{}", REPL_HELP),
+            Input::Command(Command::Unknown(name)) => eprintln!("Unknown command {}, see /help", name),
+            Input::Command(Command::System(text)) => {
+                if text.is_empty() {
+                    eprintln!("Usage: /system <text>");
+                    continue;
+                }
+                // parsed chat files keep roles as written, so this cannot be refused
+                if let Err(e) = conversation.system(text) {
+                    eprintln!("{}", e);
+                    continue;
+                }
+                overwrite_markdown_file(&filename, conversation.to_markdown(filename.clone()));
+            }
+            Input::Command(Command::Save(file)) => {
+                if let Some(mut file) = file {
+                    if !file.ends_with(".md") {
+                        file.push_str(".md");
+                    }
+                    // later writes only replace the file, so it is created here
+                    if let Err(e) = std::fs::OpenOptions::new().write(true).create(true).truncate(false).open(&file) {
+                        eprintln!("Failed to create {}: {}", file, e);
+                        continue;
+                    }
+                    filename = file;
+                    write_resume_file(&filename);
+                }
+                overwrite_markdown_file(&filename, conversation.to_markdown(filename.clone()));
+            }
+            Input::Command(Command::Retry) => {
+                if matches!(conversation.messages().last(), Some(Message::Assistant { .. })) {
+                    conversation.pop_message();
+                }
+                if !matches!(conversation.messages().last(), Some(Message::User(_))) {
+                    eprintln!("Nothing to retry.");
+                    continue;
+                }
+                send(ai, conversation, &filename).await;
+            }
+            Input::Prompt(text) => {
+                if text.trim().is_empty() {
+                    if matches!(conversation.messages().last(), Some(Message::User(_))) {
+                        send(ai, conversation, &filename).await;
+                    }
+                    continue;
+                }
+                if let Err(e) = conversation.user(text) {
+                    eprintln!("{}", e);
+                    continue;
+                }
+                send(ai, conversation, &filename).await;
+            }
+        }
+    }
+}
+
+async fn send(ai: &dyn ChatProvider, conversation: &mut Conversation, filename: &str) {
+    println!();
+    // a failed request leaves the question in place, an empty message or /retry sends it again
+    if chat_once(ai, conversation, filename).await == ChatOutcome::Failed {
+        eprintln!("Send an empty message or /retry to try again.");
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn inputs(text: &str) -> Vec<Input> {
+        let mut reader = io::Cursor::new(text.to_string());
+        let mut inputs = Vec::new();
+        loop {
+            match read_input(&mut reader, &mut io::sink()).unwrap() {
+                Input::Eof => return inputs,
+                input => inputs.push(input),
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_input() {
+        assert_eq!(
+            inputs("hello\nfirst \\\nsecond\n\"\"\"\nfn main() {\n\n}\n\"\"\"\n\n"),
+            vec![
+                Input::Prompt("hello".to_string()),
+                Input::Prompt("first \nsecond".to_string()),
+                Input::Prompt("fn main() {\n\n}".to_string()),
+                Input::Prompt(String::new()),
+            ]
+        );
+        // an unfinished block is still sent at the end of the input
+        assert_eq!(inputs("\"\"\"\nhalf"), vec![Input::Prompt("half".to_string())]);
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            inputs("/system Answer in French\n  /save notes.md\n/save\n/retry\n/quit\n/nope\n"),
+            vec![
+                Input::Command(Command::System("Answer in French".to_string())),
+                Input::Command(Command::Save(Some("notes.md".to_string()))),
+                Input::Command(Command::Save(None)),
+                Input::Command(Command::Retry),
+                Input::Command(Command::Quit),
+                Input::Command(Command::Unknown("/nope".to_string())),
+            ]
+        );
+        // only the first line of a message is taken for a command
+        assert_eq!(inputs("see \\\n/retry\n"), vec![Input::Prompt("see \n/retry".to_string())]);
+    }
+}